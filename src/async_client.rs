@@ -1,126 +1,798 @@
-use crate::get_url_from_env;
+use crate::{
+	checksum_pairs,
+	chunk_fields,
+	collapse_to_depth,
+	compare_values,
+	content_length,
+	dedup_pairs,
+	expiry_index_key,
+	file_name_to_key,
+	get_url_from_env,
+	is_transient,
+	key_to_file_name,
+	next_idempotency_key,
+	next_request_id,
+	parse_expiry_index_key,
+	retry_after,
+	unix_seconds,
+	Changes,
+	CheckResult,
+	Error,
+	Hook,
+	Options,
+	RequestInfo,
+	ResponseInfo,
+	Result,
+	CHECK_KEY,
+	EMPTY_CONFIRMATION,
+	EXPIRY_PREFIX,
+	IDEMPOTENCY_KEY_HEADER,
+	REQUEST_ID_HEADER,
+};
 
-use std::collections::HashMap;
+use std::collections::{ HashMap, HashSet, VecDeque };
+use std::fmt::Display;
+use std::future::Future;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{ Duration, Instant, SystemTime };
 
-use reqwest::Client as HttpClient;
+use futures::stream::{ self, FuturesUnordered, Stream, StreamExt, TryStreamExt };
 
+use reqwest::{ Client as HttpClient, Method, Request, RequestBuilder, Response };
+use reqwest::header::{ IF_UNMODIFIED_SINCE, LAST_MODIFIED };
+
+#[cfg(feature = "tracing")]
+use tracing::Instrument;
+
+#[cfg(feature = "cache")]
+use crate::cache::ListCache;
+
+#[cfg(feature = "serde")]
+use crate::DbValue;
+
+#[cfg(feature = "json")]
+use serde::{ de::DeserializeOwned, Serialize };
+
+use url::Url;
 use urlencoding::{ encode, decode };
 
+const DEFAULT_CONCURRENCY: usize = 16;
+const ADAPTIVE_MAX_CONCURRENCY: usize = 64;
+const ADAPTIVE_MAX_ATTEMPTS: u32 = 8;
+
 /// An asynchronous client.
-/// 
+///
 /// Provides asynchronous functions for interacting with the database.
 /// For a synchronous, blocking client, use [`SyncClient`](super::SyncClient).
-/// 
-/// ```
+///
+/// Methods borrow the client and return `Send` futures, so several operations can be
+/// driven concurrently, for example with `tokio::join!(client.get("a"), client.get("b"))`.
+///
+/// ```no_run
+/// # async fn run() -> Result<(), replitdb::Error> {
 /// let client = replitdb::AsyncClient::new();
 /// client.set("greeting", "hello world").await?;
-/// println!("{}", client.get("greeting").await?);
+/// println!("{:?}", client.get("greeting").await?);
+/// # Ok(())
+/// # }
 /// ```
 #[derive(Clone, Debug)]
 pub struct Client {
 	url: String,
 	client: HttpClient,
+	options: Options,
 }
 
 impl Client {
 	/// Create a new asynchronous client, fetching the URL from an environment variable.
-	/// 
+	///
 	/// # Panics
-	/// 
-	/// Panics if the database URL environment variable (`REPLIT_DB_URL`) is not set.
+	///
+	/// Panics if the database URL environment variable (`REPLIT_DB_URL`) is not set. Use
+	/// [`try_new`](Self::try_new) to handle this case instead.
 	pub fn new() -> Self {
-		Client {
-			url: get_url_from_env(),
+		Self::try_new().expect("REPLIT_DB_URL must be set to the database URL")
+	}
+
+	/// Create a new asynchronous client, fetching the URL from an environment variable.
+	///
+	/// Returns [`Error::MissingEnvVar`] if the database URL environment variable (`REPLIT_DB_URL`)
+	/// is not set.
+	pub fn try_new() -> Result<Self> {
+		Ok(Client {
+			url: get_url_from_env()?,
 			client: HttpClient::new(),
-		}
+			options: Options::default(),
+		})
 	}
 
 	/// Create a new asynchronous client, specifying a custom database URL.
 	pub fn new_url(url: impl Into<String>) -> Self {
+		Self::new_with_client(url, HttpClient::new())
+	}
+
+	/// Create a new asynchronous client for a custom database URL, sending requests with the provided
+	/// HTTP client instead of creating a new connection pool.
+	///
+	/// Setting the request or connect timeout replaces `client` with one built by this crate. The
+	/// read timeout is applied by this crate and keeps `client`.
+	pub fn new_with_client(url: impl Into<String>, client: HttpClient) -> Self {
 		Client {
 			url: url.into(),
-			client: HttpClient::new(),
+			client,
+			options: Options::default(),
+		}
+	}
+
+	/// Create a new asynchronous client, specifying a custom database URL.
+	/// Unlike [`new_url`](Self::new_url), returns [`Error::InvalidUrl`] if the URL is malformed.
+	pub fn try_new_url(url: impl Into<String>) -> Result<Self> {
+		let url = url.into();
+		Url::parse(&url)?;
+
+		Ok(Self::new_url(url))
+	}
+
+	/// Clone the client, pointing the clone at a different database URL.
+	/// The clone shares the HTTP connection pool and options of the original, but not its caches.
+	pub fn clone_with_url(&self, url: impl Into<String>) -> Self {
+		Client {
+			url: url.into(),
+			client: self.client.clone(),
+			options: self.options.without_caches(),
 		}
 	}
 
-	/// Get the value of the specified key. Returns `Ok(None)` if the key does not exist.
+	/// Cache the results of [`list_prefix`](Self::list_prefix) for `ttl`, keyed by prefix.
+	///
+	/// A successful `set` or `delete` of a key evicts the cached listings of every prefix the key
+	/// starts with. Writes made by other clients are not detected until the entries expire.
+	#[cfg(feature = "cache")]
+	pub fn cache_lists(mut self, ttl: Duration) -> Self {
+		self.options.list_cache = Some(Arc::new(ListCache::new(ttl)));
+		self
+	}
+
+	/// Set the timeout applied to each request, or `None` for no timeout.
+	///
+	/// This rebuilds the underlying HTTP client, so clones made before the call keep the old timeout.
+	pub fn set_timeout(&mut self, timeout: Option<Duration>) -> Result<()> {
+		self.options.timeout = timeout;
+		self.client = build_http_client(&self.options)?;
+
+		Ok(())
+	}
+
+	/// Set the timeout for establishing a connection, or `None` for no separate connect timeout.
+	/// This lets requests to an unreachable host fail quickly without limiting how long a slow
+	/// response may take once connected.
+	///
+	/// This rebuilds the underlying HTTP client, so clones made before the call keep the old timeout.
+	pub fn set_connect_timeout(&mut self, timeout: Option<Duration>) -> Result<()> {
+		self.options.connect_timeout = timeout;
+		self.client = build_http_client(&self.options)?;
+
+		Ok(())
+	}
+
+	/// Set the timeout for each read, or `None` for no read timeout. The timeout applies to waiting
+	/// for a response and to each chunk of a value read by [`get`](Self::get) and similar methods
+	/// or of a listing, restarting after every chunk, so large bodies that arrive steadily are not
+	/// cut off. When it elapses, [`Error::Timeout`] is returned.
+	pub fn set_read_timeout(&mut self, timeout: Option<Duration>) -> Result<()> {
+		self.options.read_timeout = timeout;
+
+		Ok(())
+	}
+
+	/// Prefix every key with `prefix` before any [`key_transform`](Self::key_transform), or remove
+	/// the prefix with `None`. Listed keys are returned without the prefix, so a client can be
+	/// moved between namespaces without reconstruction.
+	///
+	/// This clears the client's cached listings, which were made under the old prefix.
+	pub fn set_prefix(&mut self, prefix: Option<String>) -> Result<()> {
+		self.options = Options {
+			key_prefix: prefix,
+			..self.options.without_caches()
+		};
+
+		Ok(())
+	}
+
+	/// Set the maximum number of requests made concurrently by methods that make one request per
+	/// key, such as [`get_all`](Self::get_all) and [`empty`](Self::empty), or `None` for the default
+	/// of 16. A limit of zero is treated as one.
+	pub fn set_max_concurrency(&mut self, limit: Option<usize>) -> Result<()> {
+		self.options.max_concurrency = limit.map(|limit| limit.max(1));
+
+		Ok(())
+	}
+
+	/// Set the timeout for establishing a connection, as
+	/// [`set_connect_timeout`](Self::set_connect_timeout) does.
+	///
+	/// # Panics
+	///
+	/// Panics if the HTTP client cannot be rebuilt, as [`HttpClient::new`] does.
+	pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+		self.set_connect_timeout(Some(timeout)).expect("failed to build HTTP client");
+		self
+	}
+
+	/// Set the timeout for each read, as [`set_read_timeout`](Self::set_read_timeout) does.
+	pub fn read_timeout(mut self, timeout: Duration) -> Self {
+		self.options.read_timeout = Some(timeout);
+		self
+	}
+
+	/// Call `hook` after every request with information about the request and its response.
+	///
+	/// The hook is also called when a request fails without a response, in which case the status
+	/// and content length are `None`.
+	pub fn inspect(mut self, hook: impl Fn(&RequestInfo, &ResponseInfo) + Send + Sync + 'static) -> Self {
+		self.options.inspect = Some(Hook(Arc::new(hook)));
+		self
+	}
+
+	/// Retry failed requests up to `count` times, waiting [`retry_backoff`](Self::retry_backoff)
+	/// before the first retry and doubling the wait before each one after. By default requests are
+	/// not retried.
+	///
+	/// Reads and deletes are retried on connection errors, timeouts and `5xx` responses. Writes
+	/// are only retried if the connection could not be established, since a write whose response
+	/// was lost may already have been applied.
+	///
+	/// A connection the server or a proxy resets or closes before responding, for example with
+	/// an HTTP/2 `GOAWAY`, is reported by reqwest as a request error
+	/// ([`is_request`](reqwest::Error::is_request)) and counts as a connection error. The broken
+	/// connection is dropped from the pool, so the retry is made on a new one.
+	///
+	/// Any request answered with `429 Too Many Requests` is retried, as it was not applied, after
+	/// the delay given by the response's `Retry-After` header if there is one.
+	pub fn retries(mut self, count: u32) -> Self {
+		self.options.retries = count;
+		self
+	}
+
+	/// Set the wait before the first retry enabled by [`retries`](Self::retries). Defaults to 100 ms.
+	pub fn retry_backoff(mut self, base: Duration) -> Self {
+		self.options.retry_backoff = Some(base);
+		self
+	}
+
+	/// Make [`empty`](Self::empty), [`empty_concurrent`](Self::empty_concurrent),
+	/// [`empty_confirmed`](Self::empty_confirmed) and [`delete_prefix`](Self::delete_prefix) attempt to delete every key even if some deletes fail,
+	/// returning [`Error::DeleteFailed`] with the keys that could not be deleted at the end. By
+	/// default the first failure aborts the remaining deletes.
+	pub fn best_effort_deletes(mut self, enabled: bool) -> Self {
+		self.options.best_effort_deletes = enabled;
+		self
+	}
+
+	/// Send a unique `Idempotency-Key` header with every write, so that servers supporting it can
+	/// ignore duplicate deliveries of the same write.
+	///
+	/// Replit DB itself ignores the header; this is intended for custom backends in front of it.
+	/// A retried write is sent with the same key as the original.
+	pub fn idempotency_keys(mut self, enabled: bool) -> Self {
+		self.options.idempotency_keys = enabled;
+		self
+	}
+
+	/// Send an `Authorization: Bearer` header with the provided token on every request.
+	///
+	/// Replit DB authenticates through the database URL; this is intended for gateways in front of it.
+	pub fn bearer_token(mut self, token: impl Into<String>) -> Self {
+		self.options.bearer_token = Some(token.into());
+		self
+	}
+
+	/// Store keys longer than Replit DB's 1000 byte limit under their SHA-256 hash, so that
+	/// [`get`](Self::get), [`set`](Self::set) and [`delete`](Self::delete) accept keys of any length.
+	///
+	/// A long key is stored as `__hash__:` followed by the hex-encoded hash of the key, before any
+	/// [`key_transform`](Self::key_transform) or prefix is applied, so it stays in the same
+	/// namespace as the client's other keys. Only the hash is stored, so listing returns the hashed
+	/// form and the original key cannot be recovered from it. Distinct keys are assumed never to
+	/// share a hash.
+	pub fn hash_long_keys(mut self, enabled: bool) -> Self {
+		self.options.hash_long_keys = enabled;
+		self
+	}
+
+	/// Rewrite every key with `transform` before it is sent to the server, for example to add an
+	/// environment prefix, and map keys returned by listing back through `inverse`. This also
+	/// applies to the prefixes passed to [`list_prefix`](Self::list_prefix).
+	///
+	/// `inverse(transform(key))` must equal `key` for every key. For listing by prefix to work, the
+	/// transform must preserve prefixes, as adding a fixed prefix does.
+	pub fn key_transform(
+		mut self,
+		transform: impl Fn(&str) -> String + Send + Sync + 'static,
+		inverse: impl Fn(&str) -> String + Send + Sync + 'static,
+	) -> Self {
+		self.options.key_transform = Some((Hook(Arc::new(transform)), Hook(Arc::new(inverse))));
+		self
+	}
+
+	/// Limit the size of a single value read by [`get`](Self::get) and [`get_bytes`](Self::get_bytes)
+	/// to `limit` bytes, returning [`Error::ValueTooLarge`] for larger values instead of reading them
+	/// into memory. By default there is no limit.
+	pub fn max_value_read_bytes(mut self, limit: u64) -> Self {
+		self.options.max_value_read_bytes = Some(limit);
+		self
+	}
+
+	/// Split the requests made by [`set_many`](Self::set_many) so that each body is at most
+	/// `limit` bytes once encoded. A pair which is larger than `limit` on its own is written in a
+	/// request by itself. By default all pairs are sent in one request.
+	pub fn set_many_chunk_bytes(mut self, limit: usize) -> Self {
+		self.options.set_many_chunk_bytes = Some(limit);
+		self
+	}
+
+	/// Send the provided `Accept` header with requests made by [`get`](Self::get).
+	pub fn accept(mut self, value: impl Into<String>) -> Self {
+		self.options.accept = Some(value.into());
+		self
+	}
+
+	/// Send a unique request ID in the `X-Request-Id` header of every request, so that server logs
+	/// can be correlated with client logs. When the `tracing` feature is enabled, the same ID is
+	/// recorded as the `id` field of each request's span.
+	pub fn request_id_header(mut self, enabled: bool) -> Self {
+		self.options.request_id_header = enabled;
+		self
+	}
+
+	/// Allow writes to keys starting with a reserved prefix such as `__meta__:`.
+	/// By default, such writes return [`Error::ReservedKey`].
+	pub fn allow_reserved_keys(mut self, allow: bool) -> Self {
+		self.options.allow_reserved_keys = allow;
+		self
+	}
+
+	/// Limit the total size of the values fetched by [`get_all`](Self::get_all).
+	///
+	/// Before fetching any values, the size of each one is requested with a `HEAD` request, and
+	/// [`Error::TooLarge`] is returned if their sum exceeds `limit`. Values whose size the server
+	/// does not report are counted as empty.
+	pub fn max_total_bytes(mut self, limit: u64) -> Self {
+		self.options.max_total_bytes = Some(limit);
+		self
+	}
+
+	/// Trim leading and trailing whitespace from values returned by [`get`](Self::get),
+	/// [`get_lossy`](Self::get_lossy) and [`get_all`](Self::get_all). Off by default.
+	pub fn trim_values(mut self, trim: bool) -> Self {
+		self.options.trim_values = trim;
+		self
+	}
+
+	/// Get the value of the specified key. Returns `Ok(None)` if the key does not exist,
+	/// and `Ok(Some(String::new()))` if it exists with an empty value.
+	///
+	/// Returns [`Error::Decode`] if the value is not valid UTF-8; use [`get_lossy`](Self::get_lossy)
+	/// to replace invalid sequences instead.
 	pub async fn get(
 		&self,
 		key: impl Into<String>,
-	) -> Result<Option<String>, String> {
-		let encoded_key = encode(key.into().as_str()).into_owned();
+	) -> Result<Option<String>> {
+		match self.get_bytes(key).await? {
+			Some(bytes) => Ok(Some(self.options.trim(String::from_utf8(bytes)?))),
+			None => Ok(None),
+		}
+	}
 
-		let response = self.client.get(format!("{}/{}", self.url, encoded_key))
-			.send()
-			.await
-			.map_err(|err| err.to_string())?;
+	/// Get the value of the specified key, parsed as JSON if possible and as plain text otherwise.
+	/// Returns `Ok(None)` if the key does not exist.
+	///
+	/// Any valid JSON is parsed, so text such as `123` or `true` is returned as [`DbValue::Json`].
+	#[cfg(feature = "serde")]
+	pub async fn get_dynamic(
+		&self,
+		key: impl Into<String>,
+	) -> Result<Option<DbValue>> {
+		Ok(
+			self.get(key).await?
+				.map(|value| match serde_json::from_str(&value) {
+					Ok(json) => DbValue::Json(json),
+					Err(_) => DbValue::Text(value),
+				})
+		)
+	}
 
-		if response.status().is_success() {
-			Ok(Some(
-				response.text()
-					.await
-					.map_err(|err| err.to_string())?
-			))
-		} else if response.status().as_u16() == 404 {
-			Ok(None)
-		} else {
-			Err(
-				response.text()
-					.await
-					.map_err(|err| err.to_string())?
-			)
+	/// Get the value of the specified key, deserialized from JSON. Returns `Ok(None)` if the key
+	/// does not exist, and [`Error::Json`] if the value is not valid JSON for `T`.
+	#[cfg(feature = "json")]
+	pub async fn get_json<T: DeserializeOwned>(
+		&self,
+		key: impl Into<String>,
+	) -> Result<Option<T>> {
+		match self.get(key).await? {
+			Some(value) => Ok(Some(serde_json::from_str(&value)?)),
+			None => Ok(None),
+		}
+	}
+
+	/// Get the value of the specified key, replacing invalid UTF-8 sequences with `U+FFFD`.
+	/// Returns `Ok(None)` if the key does not exist.
+	pub async fn get_lossy(
+		&self,
+		key: impl Into<String>,
+	) -> Result<Option<String>> {
+		Ok(
+			self.get_bytes(key).await?
+				.map(|bytes| self.options.trim(String::from_utf8_lossy(&bytes).into_owned()))
+		)
+	}
+
+	/// Get the raw bytes of the value of the specified key. Returns `Ok(None)` if the key does not exist.
+	pub async fn get_bytes(
+		&self,
+		key: impl Into<String>,
+	) -> Result<Option<Vec<u8>>> {
+		match self.get_response(key.into()).await? {
+			Some(response) => Ok(Some(self.read_body(response).await?)),
+			None => Ok(None),
+		}
+	}
+
+	/// Get the value of the specified key along with its modification time, as reported by the
+	/// server's `Last-Modified` header. Returns `Ok(None)` if the key does not exist.
+	///
+	/// The time is `None` if the server does not send the header or it cannot be parsed.
+	pub async fn get_with_modified(
+		&self,
+		key: impl Into<String>,
+	) -> Result<Option<(String, Option<SystemTime>)>> {
+		let response = match self.get_response(key.into()).await? {
+			Some(response) => response,
+			None => return Ok(None),
+		};
+
+		let modified = response.headers().get(LAST_MODIFIED)
+			.and_then(|value| value.to_str().ok())
+			.and_then(|value| httpdate::parse_http_date(value).ok());
+
+		let value = String::from_utf8(self.read_body(response).await?)?;
+
+		Ok(Some((self.options.trim(value), modified)))
+	}
+
+	/// Get the first of `keys` that exists, returning it along with its value.
+	/// Returns `Ok(None)` if none of the keys exist.
+	///
+	/// Keys are tried in order, and no further requests are made once one is found.
+	pub async fn get_first(&self, keys: &[&str]) -> Result<Option<(String, String)>> {
+		for &key in keys {
+			if let Some(value) = self.get(key).await? {
+				return Ok(Some((key.to_string(), value)));
+			}
+		}
+
+		Ok(None)
+	}
+
+	/// Check whether the specified key exists, without reading its value.
+	///
+	/// A `HEAD` request is made, falling back to a `GET` whose body is discarded if the server
+	/// does not allow `HEAD`.
+	pub async fn exists(&self, key: impl Into<String>) -> Result<bool> {
+		let key = key.into();
+		let encoded_key = encode(&self.options.storage_key(&key)?).into_owned();
+
+		let request = self.client.head(format!("{}/{}", self.url, encoded_key));
+
+		let response = self.send(request, Some(&key)).await?;
+
+		match response.status().as_u16() {
+			404 => Ok(false),
+			405 => Ok(self.get_response(key).await?.is_some()),
+			_ if response.status().is_success() => Ok(true),
+			_ => Err(server_error(response).await),
 		}
 	}
 
+	/// Check which of the specified keys exist, without reading their values.
+	///
+	/// Up to 16 checks, or the limit set by [`set_max_concurrency`](Self::set_max_concurrency),
+	/// are made concurrently.
+	pub async fn exists_many<K: Into<String>>(
+		&self,
+		keys: impl IntoIterator<Item = K>,
+	) -> Result<HashMap<String, bool>> {
+		stream::iter(keys.into_iter().map(Into::into))
+			.map(|key: String| async move {
+				let exists = self.exists(key.as_str()).await?;
+				Ok((key, exists))
+			})
+			.buffer_unordered(self.concurrency())
+			.try_collect()
+			.await
+	}
+
 	/// Set the value of the specified key to the provided value.
+	///
+	/// Returns [`Error::ReservedKey`] if the key starts with a reserved prefix, unless
+	/// [`allow_reserved_keys`](Self::allow_reserved_keys) is set.
+	/// Every method taking a key returns [`Error::InvalidKey`] without making a request if the
+	/// key is empty, `.` or `..`, or contains a newline.
 	pub async fn set(
 		&self,
 		key: impl Into<String>,
 		value: impl Into<String>,
-	) -> Result<(), String> {
-		let encoded_key = encode(key.into().as_str()).into_owned();
+	) -> Result<()> {
+		let key = key.into();
+		self.options.check_reserved(&key)?;
+
+		self.set_unchecked(key, value).await
+	}
+
+	/// Set the value of the specified key, but only if it has not been modified since `since`,
+	/// returning [`Error::PreconditionFailed`] otherwise.
+	///
+	/// The condition is sent in an `If-Unmodified-Since` header, so it is only enforced by servers
+	/// that support it; Replit DB itself ignores the header and always writes the value. The time
+	/// is sent with one-second precision.
+	pub async fn set_if_unmodified_since(
+		&self,
+		key: impl Into<String>,
+		value: impl Into<String>,
+		since: SystemTime,
+	) -> Result<()> {
+		let key = key.into();
+		self.options.check_reserved(&key)?;
+
+		let encoded_key = encode(&self.options.storage_key(&key)?).into_owned();
 		let encoded_value = encode(value.into().as_str()).into_owned();
 
-		let response = self.client.post(self.url.clone())
-			.body(format!("{}={}", encoded_key, encoded_value))
-			.header("Content-Type", "application/x-www-form-urlencoded")
-			.send()
-			.await
-			.map_err(|err| err.to_string())?;
-		
+		let request = self.post(format!("{}={}", encoded_key, encoded_value))
+			.header(IF_UNMODIFIED_SINCE, httpdate::fmt_http_date(since));
+
+		let response = self.send(request, Some(&key)).await?;
+
+		if response.status().is_success() {
+			self.options.invalidate(&key);
+			Ok(())
+		} else if response.status().as_u16() == 412 {
+			Err(Error::PreconditionFailed)
+		} else {
+			Err(server_error(response).await)
+		}
+	}
+
+	/// Set the value of the specified key, to be deleted by [`sweep_expired`](Self::sweep_expired)
+	/// once `ttl` has passed.
+	///
+	/// Alongside the value, the key is written to an expiry index, under the key
+	/// `__exp__:<expiry>:<hash>` where `<expiry>` is the zero-padded Unix time in seconds and
+	/// `<hash>` is the hex SHA-256 hash of the key. Both are written in a single request. The key
+	/// stays readable until it is swept. Setting the key again with a longer TTL extends it, as the
+	/// sweep follows the entry that expires last. The index entry is not removed if the key is
+	/// later overwritten with [`set`](Self::set), so it will still be deleted.
+	pub async fn set_with_ttl(
+		&self,
+		key: impl Into<String>,
+		value: impl Into<String>,
+		ttl: Duration,
+	) -> Result<()> {
+		let key = key.into();
+		self.options.check_reserved(&key)?;
+
+		let expires = unix_seconds(SystemTime::now() + ttl);
+		let index_key = expiry_index_key(&key, expires);
+
+		let request = self.post(format!(
+			"{}={}&{}={}",
+			encode(&self.options.storage_key(&key)?),
+			encode(&value.into()),
+			encode(&self.options.storage_key(&index_key)?),
+			encode(&key),
+		));
+
+		let response = self.send(request, Some(&key)).await?;
+
 		if response.status().is_success() {
+			self.options.invalidate(&key);
+			self.options.invalidate(&index_key);
 			Ok(())
 		} else {
-			Err(
-				response.text()
-					.await
-					.map_err(|err| err.to_string())?
-			)
+			Err(server_error(response).await)
+		}
+	}
+
+	/// Delete every key written by [`set_with_ttl`](Self::set_with_ttl) whose TTL has passed,
+	/// returning the number of keys deleted.
+	///
+	/// The expiry index is listed, and only the index entries that are due are read to find their
+	/// keys, so values are never read. When a key has several entries, only the latest expiry
+	/// counts and the earlier entries are dropped once due. Each due key is deleted before its
+	/// latest index entry, so a failure part way through leaves the remaining keys to be swept by
+	/// the next call.
+	pub async fn sweep_expired(&self) -> Result<usize> {
+		let now = unix_seconds(SystemTime::now());
+
+		let mut entries: HashMap<String, Vec<(u64, String)>> = HashMap::new();
+		for index_key in self.list_prefix(EXPIRY_PREFIX).await? {
+			if let Some((expires, hash)) = parse_expiry_index_key(&index_key) {
+				let hash = hash.to_string();
+				entries.entry(hash).or_default().push((expires, index_key));
+			}
+		}
+
+		let mut count = 0;
+		for mut entries in entries.into_values() {
+			entries.sort();
+			let (latest, latest_key) = match entries.pop() {
+				Some(latest) => latest,
+				None => continue,
+			};
+
+			for (expires, index_key) in entries {
+				if expires <= now {
+					self.delete(index_key).await?;
+				}
+			}
+
+			if latest <= now {
+				if let Some(key) = self.get_bytes(latest_key.as_str()).await? {
+					self.delete(String::from_utf8(key)?).await?;
+					count += 1;
+				}
+				self.delete(latest_key).await?;
+			}
+		}
+
+		Ok(count)
+	}
+
+	/// Set the value of the specified key if `value` is `Some`, or delete the key if it is `None`.
+	pub async fn put(
+		&self,
+		key: impl Into<String>,
+		value: Option<impl Into<String>>,
+	) -> Result<()> {
+		match value {
+			Some(value) => self.set(key, value).await,
+			None => self.delete(key).await,
+		}
+	}
+
+	/// Set the value of the specified key to the formatted form of `value`.
+	pub async fn set_display<V: Display>(
+		&self,
+		key: impl Into<String>,
+		value: V,
+	) -> Result<()> {
+		self.set(key, value.to_string()).await
+	}
+
+	/// Set the value of the specified key to `value` serialized as JSON.
+	#[cfg(feature = "json")]
+	pub async fn set_json<T: Serialize + ?Sized>(
+		&self,
+		key: impl Into<String>,
+		value: &T,
+	) -> Result<()> {
+		self.set(key, serde_json::to_string(value)?).await
+	}
+
+	/// Set multiple keys to their provided values in a single request.
+	///
+	/// If a key appears more than once, only its last value is written. If
+	/// [`set_many_chunk_bytes`](Self::set_many_chunk_bytes) is set, the pairs are split across as
+	/// many requests as needed to keep each body under the limit, and if a request fails, the
+	/// pairs in earlier requests have already been written.
+	pub async fn set_many<K: Into<String>, V: Into<String>>(
+		&self,
+		pairs: impl IntoIterator<Item = (K, V)>,
+	) -> Result<()> {
+		let pairs = dedup_pairs(pairs);
+		if pairs.is_empty() {
+			return Ok(());
+		}
+
+		for (key, _) in &pairs {
+			self.options.check_reserved(key)?;
+		}
+
+		let fields = pairs.iter()
+			.map(|(key, value)| Ok(format!("{}={}", encode(&self.options.storage_key(key)?), encode(value))))
+			.collect::<Result<Vec<String>>>()?;
+
+		let result = self.set_chunks(chunk_fields(fields, self.options.set_many_chunk_bytes)).await;
+
+		for (key, _) in &pairs {
+			self.options.invalidate(key);
 		}
+
+		result
+	}
+
+	/// Add `delta` to the floating-point value of the specified key, returning the new value.
+	/// A missing key is treated as `0.0`.
+	///
+	/// Returns [`Error::Parse`] if the current value is not a number. The read and write are
+	/// separate requests, so concurrent increments of the same key may be lost.
+	pub async fn increment_float(
+		&self,
+		key: impl Into<String>,
+		delta: f64,
+	) -> Result<f64> {
+		let key = key.into();
+
+		let current = match self.get(key.clone()).await? {
+			Some(value) => value.trim().parse::<f64>().map_err(|_| Error::Parse(value))?,
+			None => 0.0,
+		};
+
+		let value = current + delta;
+		self.set(key, value.to_string()).await?;
+
+		Ok(value)
 	}
 
 	/// Delete the specified key from the database.
-	pub async fn delete(&self, key: impl Into<String>) -> Result<(), String> {
-		let encoded_key = encode(key.into().as_str()).into_owned();
+	pub async fn delete(&self, key: impl Into<String>) -> Result<()> {
+		let key = key.into();
+		let encoded_key = encode(&self.options.storage_key(&key)?).into_owned();
+
+		let request = self.client.delete(format!("{}/{}", self.url, encoded_key));
+
+		let response = self.send(request, Some(&key)).await?;
 
-		let response = self.client.delete(format!("{}/{}", self.url, encoded_key))
-			.send()
-			.await
-			.map_err(|err| err.to_string())?;
-		
 		if response.status().is_success() || response.status().as_u16() == 404 {
+			self.options.invalidate(&key);
 			Ok(())
 		} else {
-			Err(
-				response.text()
-					.await
-					.map_err(|err| err.to_string())?
-			)
+			Err(server_error(response).await)
+		}
+	}
+
+	/// Move the value of the key `from` to the key `to`, returning [`Error::MissingKey`] if `from`
+	/// does not exist.
+	///
+	/// The value is read, written to `to`, and only then deleted from `from`, so a failure never
+	/// loses the value, though it may leave it under both keys. Any existing value of `to` is
+	/// overwritten, and renaming a key to itself leaves it unchanged.
+	pub async fn rename(
+		&self,
+		from: impl Into<String>,
+		to: impl Into<String>,
+	) -> Result<()> {
+		let from = from.into();
+
+		let value = match self.get_bytes(from.as_str()).await? {
+			Some(value) => String::from_utf8(value)?,
+			None => return Err(Error::MissingKey(from)),
+		};
+
+		let to = to.into();
+		if self.options.storage_key(&from)? == self.options.storage_key(&to)? {
+			return Ok(());
 		}
+
+		self.set(to, value).await?;
+		self.delete(from).await
+	}
+
+	/// Delete each of the specified keys from the database.
+	///
+	/// Replit DB has no endpoint for deleting several keys in one request, so one request is made
+	/// per key, up to 16 at a time unless set otherwise by
+	/// [`set_max_concurrency`](Self::set_max_concurrency). The first failure aborts the remaining
+	/// deletes.
+	pub async fn delete_many<K: Into<String>>(
+		&self,
+		keys: impl IntoIterator<Item = K>,
+	) -> Result<()> {
+		self.delete_concurrent(keys, self.concurrency(), false).await
 	}
 
 	/// List all keys in the database.
-	pub async fn list(&self) -> Result<Vec<String>, String> {
+	pub async fn list(&self) -> Result<Vec<String>> {
 		self.list_prefix("").await
 	}
 
@@ -128,66 +800,1860 @@ impl Client {
 	pub async fn list_prefix(
 		&self,
 		prefix: impl Into<String>,
-	) -> Result<Vec<String>, String> {
-		let response = self.client.get(self.url.clone())
-			.query(&[ ("encode", "true"), ("prefix", prefix.into().as_str()) ])
-			.send()
-			.await
-			.map_err(|err| err.to_string())?;
-		
+	) -> Result<Vec<String>> {
+		let prefix = prefix.into();
+
+		#[cfg(feature = "cache")]
+		if let Some(keys) = self.options.list_cache.as_ref().and_then(|cache| cache.get(&prefix)) {
+			return Ok(keys);
+		}
+
+		let response = self.send(self.list_request(&prefix), None).await?;
+
 		if response.status().is_success() {
-			let text = response.text()
-				.await
-				.map_err(|err| err.to_string())?;
-
-			Ok(
-				text.split("\n")
-					.map(|key| Ok(
-						decode(key.into())
-							.map_err(|err| err.to_string())?
-							.into_owned()
-					))
-					.collect::<Result<Vec<String>, String>>()?
-			)
+			let body = self.read_limited(response, None).await?;
+			let text = String::from_utf8_lossy(&body);
+
+			let keys = text.split('\n')
+				.filter(|key| !key.is_empty())
+				.map(|key| Ok(self.options.untransform_key(decode(key)?.into_owned())))
+				.collect::<Result<Vec<String>>>()?;
+
+			#[cfg(feature = "cache")]
+			if let Some(cache) = &self.options.list_cache {
+				cache.insert(prefix, keys.clone());
+			}
+
+			Ok(keys)
 		} else {
-			Err(
-				response.text()
-					.await
-					.map_err(|err| err.to_string())?
-			)
+			Err(server_error(response).await)
 		}
 	}
 
-	/// Delete all keys in the database.
-	pub async fn empty(&self) -> Result<(), String> {
-		// this could probably be improved
+	/// Stream the keys that start with the specified prefix, decoding each one as the response
+	/// body arrives instead of collecting them all first.
+	///
+	/// The listing is requested when the stream is first polled. Listings streamed this way are
+	/// never cached. If an error is yielded, the stream ends after it.
+	pub fn list_stream(&self, prefix: impl Into<String>) -> impl Stream<Item = Result<String>> + '_ {
+		let initial: (Option<String>, Option<Response>, Vec<u8>, VecDeque<Result<String>>) =
+			(Some(prefix.into()), None, Vec::new(), VecDeque::new());
 
-		let keys = self.list().await?;
-		for key in keys {
-			self.delete(key).await?;
-		}
+		stream::unfold(initial, move |(mut prefix, mut response, mut buffer, mut keys)| async move {
+			loop {
+				if let Some(key) = keys.pop_front() {
+					if key.is_err() {
+						return Some((key, (None, None, Vec::new(), VecDeque::new())));
+					}
 
-		Ok(())
+					return Some((key, (prefix, response, buffer, keys)));
+				}
+
+				if let Some(prefix) = prefix.take() {
+					match self.send(self.list_request(&prefix), None).await {
+						Ok(listing) if listing.status().is_success() => response = Some(listing),
+						Ok(listing) => keys.push_back(Err(server_error(listing).await)),
+						Err(err) => keys.push_back(Err(err)),
+					}
+
+					continue;
+				}
+
+				let chunk = match self.with_read_timeout(response.as_mut()?.chunk()).await {
+					Ok(chunk) => chunk,
+					Err(err) => {
+						keys.push_back(Err(err));
+						continue;
+					},
+				};
+
+				match chunk {
+					Some(chunk) => buffer.extend_from_slice(&chunk),
+					None => {
+						response = None;
+						buffer.push(b'\n');
+					},
+				}
+
+				while let Some(end) = buffer.iter().position(|&byte| byte == b'\n') {
+					let line = buffer.drain(..=end).collect::<Vec<u8>>();
+					if line.len() > 1 {
+						keys.push_back(self.decode_listed_key(&line[..end]));
+					}
+				}
+			}
+		})
 	}
 
-	/// Get all key-value pairs and return them as a [`HashMap`](std::collections::HashMap).
-	pub async fn get_all(&self) -> Result<HashMap<String, String>, String> {
-		// this could probably be improved
+	/// Stream the keys that start with the specified prefix in pages of up to `page_size` keys.
+	///
+	/// Replit DB has no cursor for listings, so the pages are cut from a single
+	/// [`list_stream`](Self::list_stream) response. The body is only read as far as needed to fill
+	/// the next page, so a consumer that stops early does not download the rest of the listing.
+	/// A `page_size` of zero is treated as one. If an error is yielded, the stream ends after it.
+	pub fn list_paged_stream(
+		&self,
+		prefix: impl Into<String>,
+		page_size: usize,
+	) -> impl Stream<Item = Result<Vec<String>>> + '_ {
+		self.list_stream(prefix)
+			.chunks(page_size.max(1))
+			.map(|page| page.into_iter().collect::<Result<Vec<String>>>())
+	}
 
-		let mut out = HashMap::new();
+	/// List at most `limit` keys that start with the specified prefix.
+	///
+	/// Replit DB has no way to limit a listing, so every matching key is still downloaded and the
+	/// result is truncated afterwards. Since the server does not order keys, which keys are
+	/// returned is unspecified.
+	pub async fn list_prefix_limited(
+		&self,
+		prefix: impl Into<String>,
+		limit: usize,
+	) -> Result<Vec<String>> {
+		let mut keys = self.list_prefix(prefix).await?;
+		keys.truncate(limit);
 
-		let keys = self.list().await?;
+		Ok(keys)
+	}
+
+	/// List all keys in the database that start with `prefix` and end with `suffix`.
+	///
+	/// Only the prefix is filtered by the server; the suffix is matched after the keys are downloaded.
+	pub async fn list_matching(
+		&self,
+		prefix: &str,
+		suffix: &str,
+	) -> Result<Vec<String>> {
+		let mut keys = self.list_prefix(prefix).await?;
+		keys.retain(|key| key.ends_with(suffix));
+
+		Ok(keys)
+	}
+
+	/// List the distinct paths under `prefix`, treating `separator` as a path separator and
+	/// collapsing keys nested deeper than `max_depth` segments into their ancestor.
+	///
+	/// For example, with the keys `a:b:c`, `a:b:d` and `a:e`, listing the prefix `a:` with a
+	/// separator of `:` and a depth of 1 returns `a:b` and `a:e`. Paths are returned sorted.
+	pub async fn list_depth(
+		&self,
+		prefix: &str,
+		separator: char,
+		max_depth: usize,
+	) -> Result<Vec<String>> {
+		let keys = self.list_prefix(prefix).await?;
+
+		Ok(collapse_to_depth(prefix, keys, separator, max_depth))
+	}
+
+	/// List the key-value pairs under `prefix`, sorted by value.
+	///
+	/// Values that parse as numbers are compared numerically and sorted before all other values,
+	/// which are compared lexicographically, as is `NaN`. Every value under the prefix is fetched,
+	/// one request per key, so this is only suitable for small sets of keys.
+	pub async fn list_sorted_by_value(
+		&self,
+		prefix: &str,
+		descending: bool,
+	) -> Result<Vec<(String, String)>> {
+		let mut pairs = Vec::new();
+
+		let keys = self.list_prefix(prefix).await?;
 		for key in keys {
-			let value = self.get(key.clone()).await?;
-			out.insert(key, value.unwrap());
+			if let Some(value) = self.get(key.clone()).await? {
+				pairs.push((key, value));
+			}
 		}
 
-		Ok(out)
+		pairs.sort_by(|(_, a), (_, b)| compare_values(a, b));
+		if descending {
+			pairs.reverse();
+		}
+
+		Ok(pairs)
 	}
-}
 
-impl Default for Client {
-	fn default() -> Self {
-		Self::new()
+	/// Delete all keys in the database.
+	///
+	/// Up to 16 keys, or the limit set by [`set_max_concurrency`](Self::set_max_concurrency), are
+	/// deleted at a time, and the first failure aborts the remaining deletes unless
+	/// [`best_effort_deletes`](Self::best_effort_deletes) is set.
+	pub async fn empty(&self) -> Result<()> {
+		self.empty_concurrent(self.concurrency()).await
+	}
+
+	/// Delete all keys in the database, deleting up to `limit` keys at a time.
+	///
+	/// The first failure aborts the remaining deletes unless
+	/// [`best_effort_deletes`](Self::best_effort_deletes) is set. A `limit` of zero is treated as one.
+	pub async fn empty_concurrent(&self, limit: usize) -> Result<()> {
+		let keys = self.list().await?;
+
+		self.delete_concurrent(keys, limit, self.options.best_effort_deletes).await
+	}
+
+	/// Delete all keys that start with the specified prefix, returning the number of keys deleted.
+	///
+	/// Use [`delete_prefix_count`](Self::delete_prefix_count) to find out how many keys would be deleted first.
+	pub async fn delete_prefix(&self, prefix: impl Into<String>) -> Result<usize> {
+		let keys = self.list_prefix(prefix).await?;
+		let count = keys.len();
+
+		self.delete_concurrent(keys, 1, self.options.best_effort_deletes).await?;
+
+		Ok(count)
+	}
+
+	/// Count the keys that [`delete_prefix`](Self::delete_prefix) would delete, without deleting them.
+	pub async fn delete_prefix_count(&self, prefix: impl Into<String>) -> Result<usize> {
+		Ok(self.list_prefix(prefix).await?.len())
+	}
+
+	/// Delete all keys in the database, returning the number of keys deleted, but only if
+	/// `confirmation` is exactly [`EMPTY_CONFIRMATION`](crate::EMPTY_CONFIRMATION) (`"DELETE ALL"`).
+	///
+	/// Returns [`Error::ConfirmationRequired`] without deleting anything otherwise.
+	pub async fn empty_confirmed(&self, confirmation: &str) -> Result<usize> {
+		if confirmation != EMPTY_CONFIRMATION {
+			return Err(Error::ConfirmationRequired);
+		}
+
+		let keys = self.list().await?;
+		let count = keys.len();
+
+		self.delete_concurrent(keys, 1, self.options.best_effort_deletes).await?;
+
+		Ok(count)
+	}
+
+	/// Delete all keys in the database except those in `keep`, returning the number of keys deleted.
+	pub async fn empty_except(&self, keep: &HashSet<String>) -> Result<usize> {
+		let mut deleted = 0;
+
+		let keys = self.list().await?;
+		for key in keys.into_iter().filter(|key| !keep.contains(key)) {
+			self.delete(key).await?;
+			deleted += 1;
+		}
+
+		Ok(deleted)
+	}
+
+	/// Check the connection to the database by reading, then writing and deleting a temporary key.
+	///
+	/// Failures are reported as `false` fields of the [`CheckResult`]. Returns an error only if
+	/// the temporary key was written but could not be deleted afterwards.
+	pub async fn check(&self) -> Result<CheckResult> {
+		let mut result = CheckResult::default();
+
+		let request = self.client.get(self.url.clone())
+			.query(&[ ("encode", "true"), ("prefix", CHECK_KEY) ]);
+
+		let response = match self.send(request, None).await {
+			Ok(response) => response,
+			Err(_) => return Ok(result),
+		};
+
+		result.reachable = true;
+		result.authenticated = response.status().is_success() || response.status().as_u16() == 404;
+
+		if result.authenticated && self.set(CHECK_KEY, "").await.is_ok() {
+			result.writable = true;
+			self.delete(CHECK_KEY).await?;
+		}
+
+		Ok(result)
+	}
+
+	/// Poll the database every `interval`, yielding the changes between consecutive snapshots.
+	///
+	/// The first snapshot is taken immediately and only serves as a baseline, and polls that find
+	/// no changes yield nothing. Every poll fetches every key and value, costing one request per key
+	/// plus one to list them, so intervals should be long for large databases. Requires a Tokio
+	/// runtime with the time driver enabled.
+	pub fn changes(&self, interval: Duration) -> impl Stream<Item = Result<Changes>> + '_ {
+		let initial: (Option<HashMap<String, String>>, bool) = (None, false);
+
+		stream::unfold(initial, move |(mut previous, mut started)| async move {
+			loop {
+				if started {
+					tokio::time::sleep(interval).await;
+				}
+				started = true;
+
+				let current = match self.get_all().await {
+					Ok(current) => current,
+					Err(err) => return Some((Err(err), (previous, started))),
+				};
+
+				let changes = previous.as_ref()
+					.map(|previous| Changes::between(previous, &current));
+				previous = Some(current);
+
+				match changes {
+					Some(changes) if !changes.is_empty() => {
+						return Some((Ok(changes), (previous, started)));
+					},
+					_ => continue,
+				}
+			}
+		})
+	}
+
+	/// Compute a checksum of every key-value pair, which changes whenever any key or value does.
+	///
+	/// The checksum is the first 8 bytes of a SHA-256 digest over the pairs sorted by key, so it
+	/// is the same across runs, platforms and versions of this crate. Every value is downloaded,
+	/// as with [`get_all`](Self::get_all).
+	pub async fn checksum(&self) -> Result<u64> {
+		Ok(checksum_pairs(&self.get_all().await?))
+	}
+
+	/// Get all key-value pairs and return them as a [`HashMap`](std::collections::HashMap).
+	///
+	/// If [`max_total_bytes`](Self::max_total_bytes) is set, the sizes of all values are checked first.
+	/// Keys deleted between listing and fetching their values are left out. Up to 16 values, or
+	/// the limit set by [`set_max_concurrency`](Self::set_max_concurrency), are fetched at a time,
+	/// and the first failure aborts the remaining fetches.
+	pub async fn get_all(&self) -> Result<HashMap<String, String>> {
+		self.get_all_as(|value| value).await
+	}
+
+	/// Get all key-value pairs like [`get_all`](Self::get_all), fetching up to `limit` values at a time.
+	/// A `limit` of zero is treated as one.
+	pub async fn get_all_concurrent(&self, limit: usize) -> Result<HashMap<String, String>> {
+		self.collect_all(|value| value, |_, _| {}, limit).await
+	}
+
+	/// Get all key-value pairs, mapping each value through `f`, and return them as a [`HashMap`](std::collections::HashMap).
+	pub async fn get_all_as<T, F: Fn(String) -> T>(
+		&self,
+		f: F,
+	) -> Result<HashMap<String, T>> {
+		self.collect_all(f, |_, _| {}, self.concurrency()).await
+	}
+
+	/// Get all key-value pairs, keeping the outcome of each value fetch separately.
+	///
+	/// Only a failure to list the keys is returned as an error; a failure to fetch a value is stored
+	/// under its key, so the other values can still be used. Keys deleted before their value is
+	/// fetched are left out.
+	pub async fn get_all_results(&self) -> Result<HashMap<String, Result<String>>> {
+		let mut out = HashMap::new();
+
+		let keys = self.list().await?;
+		for key in keys {
+			match self.get(key.clone()).await {
+				Ok(Some(value)) => {
+					out.insert(key, Ok(value));
+				},
+				Ok(None) => {},
+				Err(err) => {
+					out.insert(key, Err(err));
+				},
+			}
+		}
+
+		Ok(out)
+	}
+
+	/// Get all key-value pairs, calling `progress` with the number of values fetched so far and the
+	/// total number of keys, once before the first value is fetched and again after each one.
+	pub async fn get_all_with_progress(
+		&self,
+		progress: impl Fn(usize, usize),
+	) -> Result<HashMap<String, String>> {
+		self.collect_all(|value| value, progress, self.concurrency()).await
+	}
+
+	/// Get all key-value pairs, adapting the number of concurrent requests to the server's responses.
+	///
+	/// Requests are issued using additive-increase/multiplicative-decrease: concurrency starts at one,
+	/// grows by one after each response that takes at most twice as long as the fastest seen so far,
+	/// and halves after a slower response or a `429 Too Many Requests`, up to a maximum of 64.
+	///
+	/// Rate-limited keys are retried after the delay given by the response's `Retry-After` header,
+	/// or the [`retry_backoff`](Self::retry_backoff) delay if there is none, up to 8 attempts per
+	/// key. Any other error aborts the operation.
+	pub async fn get_all_adaptive(&self) -> Result<HashMap<String, String>> {
+		let mut out = HashMap::new();
+
+		let mut pending = self.list().await?.into_iter()
+			.map(|key| (key, 0, Duration::ZERO))
+			.collect::<Vec<(String, u32, Duration)>>();
+		let mut in_flight = FuturesUnordered::new();
+		let mut limit = 1;
+		let mut fastest: Option<Duration> = None;
+
+		loop {
+			while in_flight.len() < limit {
+				let (key, attempt, delay) = match pending.pop() {
+					Some(pending) => pending,
+					None => break,
+				};
+
+				in_flight.push(async move {
+					if !delay.is_zero() {
+						tokio::time::sleep(delay).await;
+					}
+
+					let start = Instant::now();
+					let (result, retry_after) = self.get_unless_limited(key.clone()).await;
+
+					(key, attempt, result, retry_after, start.elapsed())
+				});
+			}
+
+			let (key, attempt, result, retry_after, latency) = match in_flight.next().await {
+				Some(completed) => completed,
+				None => break,
+			};
+
+			match result {
+				Ok(value) => {
+					if let Some(value) = value {
+						out.insert(key, value);
+					}
+
+					let best = fastest.map_or(latency, |fastest| fastest.min(latency));
+					fastest = Some(best);
+
+					if latency <= best * 2 {
+						limit = (limit + 1).min(ADAPTIVE_MAX_CONCURRENCY);
+					} else {
+						limit = (limit / 2).max(1);
+					}
+				},
+				Err(Error::Server { status: 429, .. }) if attempt + 1 < ADAPTIVE_MAX_ATTEMPTS => {
+					let delay = retry_after.unwrap_or_else(|| self.options.retry_delay(attempt));
+					pending.push((key, attempt + 1, delay));
+					limit = (limit / 2).max(1);
+				},
+				Err(err) => return Err(err),
+			}
+		}
+
+		Ok(out)
+	}
+
+	/// Write the value of every key to its own file in `dir`, returning the number of files written.
+	///
+	/// The directory is created if it does not exist. Each file is named after its key, with
+	/// characters that are not safe in file names percent-encoded, so that `a/b` is written to
+	/// `a%2Fb`. Existing files with the same names are overwritten.
+	pub async fn export_to_dir(&self, dir: impl AsRef<Path>) -> Result<usize> {
+		let dir = dir.as_ref();
+		tokio::fs::create_dir_all(dir).await?;
+
+		let mut count = 0;
+
+		let keys = self.list().await?;
+		for key in keys {
+			if let Some(value) = self.get_bytes(key.clone()).await? {
+				tokio::fs::write(dir.join(key_to_file_name(&key)), value).await?;
+				count += 1;
+			}
+		}
+
+		Ok(count)
+	}
+
+	/// Set a key for every file in `dir` to the file's contents, returning the number of keys set.
+	///
+	/// Keys are named after the files, reversing the encoding used by [`export_to_dir`](Self::export_to_dir).
+	/// Unless `overwrite` is set, keys that already exist are left untouched. Subdirectories and
+	/// files whose names are not valid UTF-8 are skipped. Keys with reserved prefixes are imported
+	/// like any other, so that an exported database, including its expiry index, is restored intact.
+	pub async fn import_from_dir(
+		&self,
+		dir: impl AsRef<Path>,
+		overwrite: bool,
+	) -> Result<usize> {
+		let mut count = 0;
+
+		let mut entries = tokio::fs::read_dir(dir).await?;
+		while let Some(entry) = entries.next_entry().await? {
+			if !entry.file_type().await?.is_file() {
+				continue;
+			}
+
+			let key = match entry.file_name().into_string() {
+				Ok(name) => file_name_to_key(&name)?,
+				Err(_) => continue,
+			};
+
+			if !overwrite && self.exists(key.as_str()).await? {
+				continue;
+			}
+
+			self.set_unchecked(key, tokio::fs::read_to_string(entry.path()).await?).await?;
+			count += 1;
+		}
+
+		Ok(count)
+	}
+
+	async fn collect_all<T>(
+		&self,
+		f: impl Fn(String) -> T,
+		progress: impl Fn(usize, usize),
+		concurrency: usize,
+	) -> Result<HashMap<String, T>> {
+		let mut out = HashMap::new();
+
+		let keys = self.list().await?;
+		if let Some(limit) = self.options.max_total_bytes {
+			self.check_total_size(&keys, limit).await?;
+		}
+
+		let total = keys.len();
+		progress(0, total);
+
+		let mut values = stream::iter(keys)
+			.map(|key| async move {
+				let value = self.get(key.clone()).await?;
+				Ok::<_, Error>((key, value))
+			})
+			.buffer_unordered(concurrency.max(1));
+
+		let mut fetched = 0;
+		while let Some((key, value)) = values.try_next().await? {
+			// keys deleted since listing are skipped
+			if let Some(value) = value {
+				out.insert(key, f(value));
+			}
+
+			fetched += 1;
+			progress(fetched, total);
+		}
+
+		Ok(out)
+	}
+
+	async fn delete_concurrent<K: Into<String>>(
+		&self,
+		keys: impl IntoIterator<Item = K>,
+		concurrency: usize,
+		best_effort: bool,
+	) -> Result<()> {
+		if !best_effort {
+			return stream::iter(keys)
+				.map(|key| self.delete(key))
+				.buffer_unordered(concurrency.max(1))
+				.try_collect()
+				.await;
+		}
+
+		let failed = stream::iter(keys.into_iter().map(Into::into))
+			.map(|key: String| async move {
+				let result = self.delete(key.as_str()).await;
+				result.err().map(|err| (key, err))
+			})
+			.buffer_unordered(concurrency.max(1))
+			.filter_map(|failure| async move { failure })
+			.collect::<Vec<(String, Error)>>()
+			.await;
+
+		if failed.is_empty() {
+			Ok(())
+		} else {
+			Err(Error::DeleteFailed(failed))
+		}
+	}
+
+	async fn get_response(&self, key: String) -> Result<Option<Response>> {
+		found(self.send_get(key).await?).await
+	}
+
+	async fn send_get(&self, key: String) -> Result<Response> {
+		let encoded_key = encode(&self.options.storage_key(&key)?).into_owned();
+
+		let mut request = self.client.get(format!("{}/{}", self.url, encoded_key));
+		if let Some(accept) = &self.options.accept {
+			request = request.header("Accept", accept);
+		}
+
+		self.send(request, Some(&key)).await
+	}
+
+	/// Get the value of `key` as [`get`](Self::get) does, along with the delay requested by the
+	/// `Retry-After` header if the server responds with `429 Too Many Requests`.
+	async fn get_unless_limited(&self, key: String) -> (Result<Option<String>>, Option<Duration>) {
+		let response = match self.send_get(key).await {
+			Ok(response) => response,
+			Err(err) => return (Err(err), None),
+		};
+
+		if response.status().as_u16() == 429 {
+			let delay = retry_after(response.headers());
+			return (Err(server_error(response).await), delay);
+		}
+
+		let result = async {
+			match found(response).await? {
+				Some(response) => Ok(Some(self.options.trim(String::from_utf8(self.read_body(response).await?)?))),
+				None => Ok(None),
+			}
+		};
+
+		(result.await, None)
+	}
+
+	async fn read_body(&self, response: Response) -> Result<Vec<u8>> {
+		self.read_limited(response, self.options.max_value_read_bytes).await
+	}
+
+	async fn read_limited(&self, mut response: Response, limit: Option<u64>) -> Result<Vec<u8>> {
+		if limit.is_none() && self.options.read_timeout.is_none() {
+			return Ok(response.bytes().await?.to_vec());
+		}
+
+		if let Some(limit) = limit {
+			if content_length(response.headers()).is_some_and(|length| length > limit) {
+				return Err(Error::ValueTooLarge { limit });
+			}
+		}
+
+		let mut body = Vec::new();
+		loop {
+			let chunk = match self.with_read_timeout(response.chunk()).await? {
+				Some(chunk) => chunk,
+				None => break,
+			};
+
+			if let Some(limit) = limit {
+				if body.len() as u64 + chunk.len() as u64 > limit {
+					return Err(Error::ValueTooLarge { limit });
+				}
+			}
+
+			body.extend_from_slice(&chunk);
+		}
+
+		Ok(body)
+	}
+
+	async fn with_read_timeout<T>(&self, read: impl Future<Output = reqwest::Result<T>>) -> Result<T> {
+		match self.options.read_timeout {
+			Some(timeout) => Ok(tokio::time::timeout(timeout, read).await.map_err(|_| Error::Timeout)??),
+			None => Ok(read.await?),
+		}
+	}
+
+	async fn check_total_size(&self, keys: &[String], limit: u64) -> Result<()> {
+		let mut size = 0;
+
+		for key in keys {
+			let request = self.client.head(format!("{}/{}", self.url, encode(&self.options.storage_key(key)?)));
+			let response = self.send(request, Some(key)).await?;
+
+			if response.status().as_u16() == 404 {
+				continue;
+			}
+			if !response.status().is_success() {
+				return Err(server_error(response).await);
+			}
+
+			size += content_length(response.headers()).unwrap_or(0);
+			if size > limit {
+				return Err(Error::TooLarge { size, limit });
+			}
+		}
+
+		Ok(())
+	}
+
+	async fn set_chunks(&self, chunks: Vec<String>) -> Result<()> {
+		for body in chunks {
+			let request = self.post(body);
+
+			let response = self.send(request, None).await?;
+
+			if !response.status().is_success() {
+				return Err(server_error(response).await);
+			}
+		}
+
+		Ok(())
+	}
+
+	fn list_request(&self, prefix: &str) -> RequestBuilder {
+		self.client.get(self.url.clone())
+			.query(&[ ("encode", "true"), ("prefix", self.options.transform_key(prefix).as_str()) ])
+	}
+
+	fn decode_listed_key(&self, line: &[u8]) -> Result<String> {
+		let line = String::from_utf8(line.to_vec())?;
+
+		Ok(self.options.untransform_key(decode(&line)?.into_owned()))
+	}
+
+	async fn set_unchecked(&self, key: String, value: impl Into<String>) -> Result<()> {
+		let encoded_key = encode(&self.options.storage_key(&key)?).into_owned();
+		let encoded_value = encode(value.into().as_str()).into_owned();
+
+		let request = self.post(format!("{}={}", encoded_key, encoded_value));
+
+		let response = self.send(request, Some(&key)).await?;
+
+		if response.status().is_success() {
+			self.options.invalidate(&key);
+			Ok(())
+		} else {
+			Err(server_error(response).await)
+		}
+	}
+
+	fn concurrency(&self) -> usize {
+		self.options.max_concurrency.unwrap_or(DEFAULT_CONCURRENCY)
+	}
+
+	fn post(&self, body: String) -> RequestBuilder {
+		let request = self.client.post(self.url.clone())
+			.body(body)
+			.header("Content-Type", "application/x-www-form-urlencoded");
+
+		if self.options.idempotency_keys {
+			request.header(IDEMPOTENCY_KEY_HEADER, next_idempotency_key())
+		} else {
+			request
+		}
+	}
+
+	async fn send(
+		&self,
+		mut request: RequestBuilder,
+		key: Option<&str>,
+	) -> Result<Response> {
+		let id = next_request_id();
+		if self.options.request_id_header {
+			request = request.header(REQUEST_ID_HEADER, id.as_str());
+		}
+		if let Some(token) = &self.options.bearer_token {
+			request = request.bearer_auth(token);
+		}
+
+		let response = self.execute_with_retries(request.build()?, key);
+
+		#[cfg(feature = "tracing")]
+		let response = response.instrument(tracing::debug_span!("request", id = %id));
+
+		response.await
+	}
+
+	async fn execute_with_retries(
+		&self,
+		mut request: Request,
+		key: Option<&str>,
+	) -> Result<Response> {
+		let idempotent = request.method() != Method::POST;
+
+		let mut attempt = 0;
+		loop {
+			let retry = if attempt < self.options.retries { request.try_clone() } else { None };
+
+			let response = self.execute(request, key);
+			let result = match self.options.read_timeout {
+				Some(timeout) => tokio::time::timeout(timeout, response).await.unwrap_or(Err(Error::Timeout)),
+				None => response.await,
+			};
+
+			let next = match retry {
+				Some(next) => next,
+				None => return result,
+			};
+
+			// A rate-limited request was not applied, so even writes can be retried
+			let delay = match &result {
+				Ok(response) if response.status().as_u16() == 429 => retry_after(response.headers()),
+				Ok(response) if idempotent && response.status().is_server_error() => None,
+				Err(err) if is_transient(err, idempotent) => None,
+				_ => return result,
+			};
+
+			tokio::time::sleep(delay.unwrap_or_else(|| self.options.retry_delay(attempt))).await;
+			request = next;
+			attempt += 1;
+		}
+	}
+
+	async fn execute(
+		&self,
+		request: Request,
+		key: Option<&str>,
+	) -> Result<Response> {
+		let hook = match &self.options.inspect {
+			Some(hook) => hook,
+			None => return Ok(self.client.execute(request).await?),
+		};
+
+		let request_info = RequestInfo {
+			method: request.method().clone(),
+			key: key.map(String::from),
+			body_len: request.body().and_then(|body| body.as_bytes()).map_or(0, <[u8]>::len),
+		};
+
+		let start = Instant::now();
+		let result = self.client.execute(request).await;
+
+		let response_info = ResponseInfo {
+			status: result.as_ref().ok().map(|response| response.status().as_u16()),
+			content_length: result.as_ref().ok().and_then(|response| response.content_length()),
+			elapsed: start.elapsed(),
+		};
+		(hook.0)(&request_info, &response_info);
+
+		Ok(result?)
+	}
+}
+
+impl Default for Client {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+async fn server_error(response: Response) -> Error {
+	let status = response.status().as_u16();
+
+	match response.text().await {
+		Ok(body) => Error::Server { status, body },
+		Err(err) => err.into(),
+	}
+}
+
+async fn found(response: Response) -> Result<Option<Response>> {
+	if response.status().is_success() {
+		Ok(Some(response))
+	} else if response.status().as_u16() == 404 {
+		Ok(None)
+	} else {
+		Err(server_error(response).await)
+	}
+}
+
+fn build_http_client(options: &Options) -> Result<HttpClient> {
+	let mut builder = HttpClient::builder();
+	if let Some(timeout) = options.timeout {
+		builder = builder.timeout(timeout);
+	}
+	if let Some(timeout) = options.connect_timeout {
+		builder = builder.connect_timeout(timeout);
+	}
+
+	Ok(builder.build()?)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::mock::{ MockResponse, MockServer };
+
+	use std::time::UNIX_EPOCH;
+
+	#[test]
+	fn try_new_url_accepts_valid_url() {
+		assert!(Client::try_new_url("https://kv.replit.com/v0/token").is_ok());
+	}
+
+	#[test]
+	fn try_new_url_rejects_invalid_url() {
+		assert!(matches!(Client::try_new_url("not a url"), Err(Error::InvalidUrl(_))));
+	}
+
+	async fn check_with_read_status(status: u16) -> CheckResult {
+		let server = MockServer::with_handler(move |request, _| match (request.method.as_str(), &request.key) {
+			("GET", None) => Some(MockResponse::status(status)),
+			_ => None,
+		});
+
+		Client::new_url(server.url()).check().await.unwrap()
+	}
+
+	#[tokio::test]
+	async fn check_maps_read_status() {
+		let all = CheckResult { reachable: true, authenticated: true, writable: true };
+		let unauthenticated = CheckResult { reachable: true, authenticated: false, writable: false };
+
+		assert_eq!(check_with_read_status(200).await, all);
+		assert_eq!(check_with_read_status(401).await, unauthenticated);
+		assert_eq!(check_with_read_status(500).await, unauthenticated);
+	}
+
+	#[tokio::test]
+	async fn set_rejects_reserved_key() {
+		let client = Client::new_url("http://127.0.0.1:9");
+
+		assert!(matches!(client.set("__meta__:foo", "bar").await, Err(Error::ReservedKey(key)) if key == "__meta__:foo"));
+	}
+
+	#[tokio::test]
+	async fn empty_confirmed_requires_confirmation() {
+		let server = MockServer::start();
+		server.insert("a", "1");
+		server.insert("b", "2");
+		let client = Client::new_url(server.url());
+
+		assert!(matches!(client.empty_confirmed("delete all").await, Err(Error::ConfirmationRequired)));
+		assert_eq!(server.values().len(), 2);
+
+		assert_eq!(client.empty_confirmed(EMPTY_CONFIRMATION).await.unwrap(), 2);
+		assert!(server.values().is_empty());
+	}
+
+	#[tokio::test]
+	async fn rejects_invalid_keys_without_requests() {
+		let server = MockServer::start();
+		let client = Client::new_url(server.url());
+
+		for key in &[ "", "a\nb" ] {
+			assert!(matches!(client.get(*key).await, Err(Error::InvalidKey(_))));
+			assert!(matches!(client.set(*key, "value").await, Err(Error::InvalidKey(_))));
+			assert!(matches!(client.delete(*key).await, Err(Error::InvalidKey(_))));
+		}
+
+		assert!(server.requests().is_empty());
+	}
+
+	fn assert_send<T: Send>(value: T) -> T {
+		value
+	}
+
+	#[tokio::test]
+	async fn futures_are_send_and_join() {
+		let server = MockServer::start();
+		let client = Client::new_url(server.url());
+
+		let (a, b) = tokio::join!(
+			assert_send(client.set("a", "1")),
+			assert_send(client.set("b", "2")),
+		);
+		a.unwrap();
+		b.unwrap();
+
+		let (a, b, c) = tokio::join!(
+			assert_send(client.get("a")),
+			assert_send(client.get("b")),
+			assert_send(client.delete("c")),
+		);
+		assert_eq!(a.unwrap().as_deref(), Some("1"));
+		assert_eq!(b.unwrap().as_deref(), Some("2"));
+		c.unwrap();
+	}
+
+	#[tokio::test]
+	async fn changes_yields_modified_key() {
+		let server = MockServer::start();
+		server.insert("a", "1");
+		let client = Client::new_url(server.url());
+
+		let changes = client.changes(Duration::from_millis(10));
+		futures::pin_mut!(changes);
+
+		let writer = Client::new_url(server.url());
+		let (changes, _) = tokio::join!(changes.next(), async {
+			tokio::time::sleep(Duration::from_millis(50)).await;
+			writer.set("a", "2").await.unwrap();
+		});
+
+		let changes = changes.unwrap().unwrap();
+		assert_eq!(changes.modified.get("a").map(String::as_str), Some("2"));
+		assert!(changes.added.is_empty() && changes.removed.is_empty());
+	}
+
+	#[tokio::test]
+	async fn get_rejects_invalid_utf8() {
+		let server = MockServer::start();
+		server.insert("a", vec![ b'o', b'k', 0xff ]);
+		let client = Client::new_url(server.url());
+
+		assert!(matches!(client.get("a").await, Err(Error::Decode(_))));
+		assert_eq!(client.get_lossy("a").await.unwrap().as_deref(), Some("ok\u{fffd}"));
+	}
+
+	#[tokio::test]
+	async fn get_enforces_read_limit() {
+		let server = MockServer::start();
+		server.insert("small", "0123");
+		server.insert("large", "0123456789");
+		let client = Client::new_url(server.url()).max_value_read_bytes(4);
+
+		assert_eq!(client.get("small").await.unwrap().as_deref(), Some("0123"));
+		assert!(matches!(client.get("large").await, Err(Error::ValueTooLarge { limit: 4 })));
+		assert!(matches!(client.get_bytes("large").await, Err(Error::ValueTooLarge { limit: 4 })));
+	}
+
+	fn failing_twice() -> MockServer {
+		let failures = std::sync::atomic::AtomicUsize::new(0);
+
+		MockServer::with_handler(move |_, _| {
+			if failures.fetch_add(1, std::sync::atomic::Ordering::SeqCst) < 2 {
+				Some(MockResponse::status(503))
+			} else {
+				None
+			}
+		})
+	}
+
+	#[tokio::test]
+	async fn retries_reads_after_server_errors() {
+		let server = failing_twice();
+		server.insert("a", "1");
+		let client = Client::new_url(server.url())
+			.retries(2)
+			.retry_backoff(Duration::from_millis(1));
+
+		assert_eq!(client.get("a").await.unwrap().as_deref(), Some("1"));
+		assert_eq!(server.requests().len(), 3);
+	}
+
+	#[tokio::test]
+	async fn does_not_retry_writes_after_a_response() {
+		let server = failing_twice();
+		let client = Client::new_url(server.url())
+			.retries(2)
+			.retry_backoff(Duration::from_millis(1));
+
+		assert!(matches!(client.set("a", "1").await, Err(Error::Server { status: 503, .. })));
+		assert_eq!(server.requests().len(), 1);
+	}
+
+	#[tokio::test]
+	async fn best_effort_empty_reports_failed_deletes() {
+		let server = MockServer::with_handler(|request, _| {
+			if request.method == "DELETE" && request.key.as_deref() == Some("stuck") {
+				Some(MockResponse::status(500))
+			} else {
+				None
+			}
+		});
+		for key in &[ "a", "stuck", "b" ] {
+			server.insert(key, "1");
+		}
+		let client = Client::new_url(server.url()).best_effort_deletes(true);
+
+		match client.empty().await {
+			Err(Error::DeleteFailed(failed)) => {
+				assert_eq!(failed.len(), 1);
+				assert_eq!(failed[0].0, "stuck");
+			},
+			other => panic!("unexpected result: {:?}", other),
+		}
+		assert_eq!(server.values().keys().collect::<Vec<&String>>(), vec![ "stuck" ]);
+	}
+
+	#[tokio::test]
+	async fn key_transform_round_trips_listed_keys() {
+		let server = MockServer::start();
+		server.insert("prod:a", "1");
+		server.insert("prod:b", "2");
+		server.insert("dev:a", "3");
+		let client = Client::new_url(server.url()).key_transform(
+			|key| format!("prod:{}", key),
+			|key| key.strip_prefix("prod:").unwrap_or(key).to_string(),
+		);
+
+		assert_eq!(client.list().await.unwrap(), vec![ "a", "b" ]);
+		assert_eq!(client.get("a").await.unwrap().as_deref(), Some("1"));
+
+		let all = client.get_all().await.unwrap();
+		assert_eq!(all.len(), 2);
+		assert_eq!(all["b"], "2");
+
+		client.empty().await.unwrap();
+		assert_eq!(server.values().keys().collect::<Vec<&String>>(), vec![ "dev:a" ]);
+	}
+
+	#[tokio::test]
+	async fn rename_moves_value() {
+		let server = MockServer::start();
+		server.insert("a", "1");
+		let client = Client::new_url(server.url());
+
+		client.rename("a", "b").await.unwrap();
+		assert_eq!(server.values().into_iter().collect::<Vec<(String, String)>>(), vec![ ("b".to_string(), "1".to_string()) ]);
+	}
+
+	#[tokio::test]
+	async fn rename_reports_missing_source() {
+		let server = MockServer::start();
+		server.insert("b", "1");
+		let client = Client::new_url(server.url());
+
+		assert!(matches!(client.rename("a", "b").await, Err(Error::MissingKey(key)) if key == "a"));
+		assert_eq!(server.values()["b"], "1");
+	}
+
+	#[tokio::test]
+	async fn rename_to_itself_keeps_value() {
+		let server = MockServer::start();
+		server.insert("a", "1");
+		let client = Client::new_url(server.url());
+
+		client.rename("a", "a").await.unwrap();
+		assert_eq!(server.values()["a"], "1");
+	}
+
+	#[tokio::test]
+	async fn get_all_adaptive_waits_for_retry_after() {
+		let limited = Arc::new(std::sync::Mutex::new(None::<Instant>));
+		let handler_limited = limited.clone();
+		let server = MockServer::with_handler(move |request, _| {
+			if request.method != "GET" || request.key.as_deref() != Some("a") {
+				return None;
+			}
+
+			let mut limited = handler_limited.lock().unwrap();
+			match *limited {
+				Some(at) => {
+					assert!(at.elapsed() >= Duration::from_secs(1));
+					None
+				},
+				None => {
+					*limited = Some(Instant::now());
+					Some(MockResponse::status(429).header("Retry-After", "1"))
+				},
+			}
+		});
+		server.insert("a", "1");
+		server.insert("b", "2");
+		let client = Client::new_url(server.url());
+
+		let all = client.get_all_adaptive().await.unwrap();
+		assert_eq!(all.len(), 2);
+		assert_eq!(all["a"], "1");
+		assert!(limited.lock().unwrap().is_some());
+	}
+
+	#[tokio::test]
+	async fn get_all_adaptive_gives_up_after_max_attempts() {
+		let server = MockServer::with_handler(|request, _| match (request.method.as_str(), &request.key) {
+			("GET", Some(_)) => Some(MockResponse::status(429)),
+			_ => None,
+		});
+		server.insert("a", "1");
+		let client = Client::new_url(server.url()).retry_backoff(Duration::from_millis(1));
+
+		assert!(matches!(client.get_all_adaptive().await, Err(Error::Server { status: 429, .. })));
+		assert_eq!(server.requests().len(), 1 + ADAPTIVE_MAX_ATTEMPTS as usize);
+	}
+
+	#[tokio::test]
+	async fn sweep_expired_deletes_only_due_keys() {
+		let server = MockServer::start();
+		let client = Client::new_url(server.url()).hash_long_keys(true);
+		let long_key = "k".repeat(2000);
+
+		client.set_with_ttl("due", "1", Duration::ZERO).await.unwrap();
+		client.set_with_ttl(long_key.as_str(), "2", Duration::ZERO).await.unwrap();
+		client.set_with_ttl("later", "3", Duration::from_secs(3600)).await.unwrap();
+		assert_eq!(server.values().len(), 6);
+
+		assert_eq!(client.sweep_expired().await.unwrap(), 2);
+
+		let values = server.values();
+		assert_eq!(values.len(), 2);
+		assert_eq!(values["later"], "3");
+		assert!(values.iter().any(|(key, value)| key.starts_with(EXPIRY_PREFIX) && value == "later"));
+	}
+
+	#[tokio::test]
+	async fn export_and_import_round_trip() {
+		let source = MockServer::start();
+		for &(key, value) in &[ ("a/b", "1"), ("a.b", "2"), ("plain", "3"), ("__exp__:00000000000000000001:x", "x") ] {
+			source.insert(key, value);
+		}
+		let destination = MockServer::start();
+		let dir = std::env::temp_dir().join(format!("replitdb-async-export-{}", std::process::id()));
+
+		assert_eq!(Client::new_url(source.url()).export_to_dir(&dir).await.unwrap(), 4);
+		let imported = Client::new_url(destination.url()).import_from_dir(&dir, false).await;
+		std::fs::remove_dir_all(&dir).unwrap();
+
+		assert_eq!(imported.unwrap(), 4);
+		assert_eq!(destination.values(), source.values());
+	}
+
+	#[tokio::test]
+	async fn empty_listing_has_no_keys() {
+		let server = MockServer::start();
+		let client = Client::new_url(server.url());
+
+		assert_eq!(client.list().await.unwrap(), Vec::<String>::new());
+		assert_eq!(client.delete_prefix_count("a").await.unwrap(), 0);
+		assert_eq!(client.delete_prefix("a").await.unwrap(), 0);
+	}
+
+	#[tokio::test]
+	async fn delete_prefix_counts_deleted_keys() {
+		let server = MockServer::start();
+		for key in &[ "a1", "a2", "b1" ] {
+			server.insert(key, "1");
+		}
+		let client = Client::new_url(server.url());
+
+		assert_eq!(client.delete_prefix_count("a").await.unwrap(), 2);
+		assert_eq!(client.delete_prefix("a").await.unwrap(), 2);
+		assert_eq!(server.values().keys().collect::<Vec<&String>>(), vec![ "b1" ]);
+	}
+
+	#[tokio::test]
+	async fn list_paged_stream_splits_pages() {
+		let server = MockServer::start();
+		for key in &[ "a1", "a2", "a3", "a4", "a5", "b1" ] {
+			server.insert(key, "1");
+		}
+		let client = Client::new_url(server.url());
+
+		let pages = client.list_paged_stream("a", 2).try_collect::<Vec<Vec<String>>>().await.unwrap();
+		assert_eq!(pages, vec![ vec![ "a1", "a2" ], vec![ "a3", "a4" ], vec![ "a5" ] ]);
+
+		let pages = client.list_paged_stream("a", 5).try_collect::<Vec<Vec<String>>>().await.unwrap();
+		assert_eq!(pages, vec![ vec![ "a1", "a2", "a3", "a4", "a5" ] ]);
+
+		let pages = client.list_paged_stream("c", 2).try_collect::<Vec<Vec<String>>>().await.unwrap();
+		assert!(pages.is_empty());
+	}
+
+	#[tokio::test]
+	async fn set_prefix_changes_namespace() {
+		let server = MockServer::start();
+		server.insert("other", "0");
+		let mut client = Client::new_url(server.url());
+
+		client.set_prefix(Some("app:".to_string())).unwrap();
+		client.set("a", "1").await.unwrap();
+		assert_eq!(client.list().await.unwrap(), vec![ "a" ]);
+		assert_eq!(client.get("a").await.unwrap().as_deref(), Some("1"));
+
+		client.set_prefix(None).unwrap();
+		assert_eq!(client.list().await.unwrap(), vec![ "app:a", "other" ]);
+		assert_eq!(client.get("a").await.unwrap(), None);
+	}
+
+	#[tokio::test]
+	async fn set_max_concurrency_limits_requests_in_flight() {
+		let server = MockServer::start();
+		for key in 0..8 {
+			server.insert(&key.to_string(), "1");
+		}
+		server.set_delay(Duration::from_millis(50));
+		let mut client = Client::new_url(server.url());
+
+		client.set_max_concurrency(Some(2)).unwrap();
+		assert_eq!(client.get_all().await.unwrap().len(), 8);
+		assert_eq!(server.peak_in_flight(), 2);
+
+		client.set_max_concurrency(None).unwrap();
+		assert_eq!(client.get_all().await.unwrap().len(), 8);
+		assert!(server.peak_in_flight() > 2);
+	}
+
+	#[tokio::test]
+	async fn long_keys_are_hashed_inside_the_namespace() {
+		let server = MockServer::start();
+		server.insert("dev:a", "0");
+		let long_key = "k".repeat(2000);
+
+		let client = Client::new_url(server.url())
+			.hash_long_keys(true)
+			.key_transform(
+				|key| format!("prod:{}", key),
+				|key| key.strip_prefix("prod:").unwrap_or(key).to_string(),
+			);
+		client.set(long_key.as_str(), "1").await.unwrap();
+		assert!(server.values().keys().any(|key| key.starts_with("prod:__hash__:")));
+		assert_eq!(client.get(long_key.as_str()).await.unwrap().as_deref(), Some("1"));
+
+		let mut client = Client::new_url(server.url()).hash_long_keys(true);
+		client.set_prefix(Some("app:".to_string())).unwrap();
+		client.set(long_key.as_str(), "2").await.unwrap();
+		assert!(server.values().keys().any(|key| key.starts_with("app:__hash__:")));
+		assert_eq!(client.list().await.unwrap().len(), 1);
+
+		client.empty().await.unwrap();
+		assert_eq!(server.values().len(), 2);
+		assert!(server.values().keys().all(|key| !key.starts_with("app:")));
+
+		let client = Client::new_url(server.url())
+			.hash_long_keys(true)
+			.key_transform(
+				|key| format!("prod:{}", key),
+				|key| key.strip_prefix("prod:").unwrap_or(key).to_string(),
+			);
+		client.empty().await.unwrap();
+		assert_eq!(server.values().keys().collect::<Vec<&String>>(), vec![ "dev:a" ]);
+	}
+
+	#[tokio::test]
+	async fn sweep_expired_follows_extended_ttl() {
+		let server = MockServer::start();
+		let client = Client::new_url(server.url());
+
+		client.set_with_ttl("k", "1", Duration::ZERO).await.unwrap();
+		client.set_with_ttl("k", "2", Duration::from_secs(86400)).await.unwrap();
+		assert_eq!(server.values().len(), 3);
+
+		assert_eq!(client.sweep_expired().await.unwrap(), 0);
+
+		let values = server.values();
+		assert_eq!(values.len(), 2);
+		assert_eq!(values["k"], "2");
+	}
+
+	#[tokio::test]
+	async fn retries_rate_limited_requests_after_retry_after() {
+		let server = MockServer::with_handler(|request, state| {
+			if state.requests.iter().filter(|seen| seen.method == request.method).count() == 1 {
+				Some(MockResponse::status(429).header("Retry-After", "2"))
+			} else {
+				None
+			}
+		});
+		let client = Client::new_url(server.url()).retries(1);
+
+		let start = Instant::now();
+		client.set("a", "1").await.unwrap();
+		assert!(start.elapsed() >= Duration::from_secs(2));
+
+		let start = Instant::now();
+		assert_eq!(client.get("a").await.unwrap().as_deref(), Some("1"));
+		assert!(start.elapsed() >= Duration::from_secs(2));
+		assert_eq!(server.requests().len(), 4);
+	}
+
+	#[tokio::test]
+	async fn get_all_skips_keys_deleted_after_listing() {
+		let server = MockServer::with_handler(|request, state| match (request.method.as_str(), &request.key) {
+			("GET", Some(key)) if key == "b" => {
+				state.values.remove("b");
+				Some(MockResponse::status(404))
+			},
+			_ => None,
+		});
+		for key in &[ "a", "b", "c" ] {
+			server.insert(key, "1");
+		}
+		let client = Client::new_url(server.url());
+
+		let all = client.get_all().await.unwrap();
+		let mut keys = all.keys().collect::<Vec<&String>>();
+		keys.sort();
+		assert_eq!(keys, vec![ "a", "c" ]);
+	}
+
+	#[tokio::test]
+	async fn exists_checks_with_head() {
+		let server = MockServer::start();
+		server.insert("present", "value");
+		let client = Client::new_url(server.url());
+
+		assert!(client.exists("present").await.unwrap());
+		assert!(!client.exists("absent").await.unwrap());
+		assert!(server.requests().iter().all(|request| request.method == "HEAD"));
+	}
+
+	#[tokio::test]
+	async fn exists_falls_back_to_get_without_head() {
+		let server = MockServer::with_handler(|request, _| {
+			if request.method == "HEAD" {
+				Some(MockResponse::status(405))
+			} else {
+				None
+			}
+		});
+		server.insert("present", "value");
+		let client = Client::new_url(server.url());
+
+		assert!(client.exists("present").await.unwrap());
+		assert!(!client.exists("absent").await.unwrap());
+
+		let methods = server.requests().into_iter().map(|request| request.method).collect::<Vec<String>>();
+		assert_eq!(methods, vec![ "HEAD", "GET", "HEAD", "GET" ]);
+	}
+
+	#[cfg(feature = "json")]
+	#[derive(Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+	struct Point {
+		x: i32,
+		label: String,
+	}
+
+	#[cfg(feature = "json")]
+	#[tokio::test]
+	async fn json_round_trips_a_struct() {
+		let server = MockServer::start();
+		let client = Client::new_url(server.url());
+		let point = Point { x: 3, label: "a & b".to_string() };
+
+		client.set_json("point", &point).await.unwrap();
+		assert_eq!(server.values()["point"], r#"{"x":3,"label":"a & b"}"#);
+		assert_eq!(client.get_json::<Point>("point").await.unwrap(), Some(point));
+	}
+
+	#[cfg(feature = "json")]
+	#[tokio::test]
+	async fn get_json_reports_invalid_and_missing_values() {
+		let server = MockServer::start();
+		server.insert("point", "not json");
+		let client = Client::new_url(server.url());
+
+		assert!(matches!(client.get_json::<Point>("point").await, Err(Error::Json(_))));
+		assert_eq!(client.get_json::<Point>("missing").await.unwrap(), None);
+	}
+
+	#[tokio::test]
+	async fn connect_timeout_fails_fast_on_unroutable_address() {
+		let client = Client::new_url("http://10.255.255.1")
+			.connect_timeout(Duration::from_millis(200))
+			.read_timeout(Duration::from_secs(5));
+
+		let start = Instant::now();
+		assert!(matches!(client.get("a").await, Err(Error::Http(_))));
+		assert!(start.elapsed() < Duration::from_secs(3));
+	}
+
+	#[tokio::test]
+	async fn read_timeout_covers_listing_bodies() {
+		let server = MockServer::with_handler(|request, _| match (request.method.as_str(), &request.key) {
+			("GET", None) => Some(
+				MockResponse::status(200)
+					.body("a\nb")
+					.stall(Duration::from_secs(1))
+			),
+			_ => None,
+		});
+		let client = Client::new_url(server.url())
+			.connect_timeout(Duration::from_secs(1))
+			.read_timeout(Duration::from_millis(100));
+
+		assert!(matches!(client.list().await, Err(Error::Timeout)));
+		assert!(matches!(client.list_stream("").try_collect::<Vec<String>>().await, Err(Error::Timeout)));
+	}
+
+	#[tokio::test]
+	async fn retries_reads_after_connection_reset() {
+		let server = MockServer::with_handler(|_, state| {
+			if state.requests.len() == 1 {
+				Some(MockResponse::reset())
+			} else {
+				None
+			}
+		});
+		server.insert("a", "1");
+		let client = Client::new_url(server.url())
+			.retries(1)
+			.retry_backoff(Duration::from_millis(1));
+
+		assert_eq!(client.get("a").await.unwrap().as_deref(), Some("1"));
+		assert_eq!(server.requests().len(), 2);
+	}
+
+	fn conditional_server() -> MockServer {
+		// Keys are taken to have been modified at the Unix epoch plus 1000 seconds
+		MockServer::with_handler(|request, _| {
+			let since = httpdate::parse_http_date(request.header("If-Unmodified-Since")?).unwrap();
+
+			if since < UNIX_EPOCH + Duration::from_secs(1000) {
+				Some(MockResponse::status(412))
+			} else {
+				None
+			}
+		})
+	}
+
+	#[tokio::test]
+	async fn set_if_unmodified_since_writes_when_unmodified() {
+		let server = conditional_server();
+		let client = Client::new_url(server.url());
+		let since = UNIX_EPOCH + Duration::from_secs(2000);
+
+		client.set_if_unmodified_since("a", "1", since).await.unwrap();
+		assert_eq!(server.values()["a"], "1");
+		assert_eq!(server.requests()[0].header("If-Unmodified-Since"), Some("Thu, 01 Jan 1970 00:33:20 GMT"));
+	}
+
+	#[tokio::test]
+	async fn set_if_unmodified_since_maps_precondition_failed() {
+		let server = conditional_server();
+		let client = Client::new_url(server.url());
+		let since = UNIX_EPOCH + Duration::from_secs(500);
+
+		assert!(matches!(client.set_if_unmodified_since("a", "1", since).await, Err(Error::PreconditionFailed)));
+		assert!(server.values().is_empty());
+	}
+
+	#[tokio::test]
+	async fn get_all_results_keeps_other_values_after_a_failure() {
+		let server = MockServer::with_handler(|request, _| match (request.method.as_str(), &request.key) {
+			("GET", Some(key)) if key == "b" => Some(MockResponse::status(500)),
+			_ => None,
+		});
+		for key in &[ "a", "b", "c" ] {
+			server.insert(key, key.to_uppercase());
+		}
+		let client = Client::new_url(server.url());
+
+		let results = client.get_all_results().await.unwrap();
+		assert_eq!(results.len(), 3);
+		assert_eq!(results["a"].as_deref().unwrap(), "A");
+		assert_eq!(results["c"].as_deref().unwrap(), "C");
+		assert!(matches!(results["b"], Err(Error::Server { status: 500, .. })));
+	}
+
+	#[cfg(feature = "serde")]
+	#[tokio::test]
+	async fn get_dynamic_distinguishes_json_and_text() {
+		let server = MockServer::start();
+		server.insert("json", r#"{"a":[1,2]}"#);
+		server.insert("text", "hello world");
+		let client = Client::new_url(server.url());
+
+		match client.get_dynamic("json").await.unwrap() {
+			Some(DbValue::Json(json)) => assert_eq!(json["a"][1], 2),
+			other => panic!("unexpected value: {:?}", other),
+		}
+		assert!(matches!(client.get_dynamic("text").await.unwrap(), Some(DbValue::Text(text)) if text == "hello world"));
+		assert!(client.get_dynamic("missing").await.unwrap().is_none());
+	}
+
+	#[cfg(feature = "cache")]
+	#[tokio::test]
+	async fn set_invalidates_cached_listings_under_its_prefix() {
+		let server = MockServer::start();
+		server.insert("a1", "1");
+		server.insert("b1", "1");
+		let client = Client::new_url(server.url()).cache_lists(Duration::from_secs(60));
+		let listings = || server.requests().iter()
+			.filter(|request| request.method == "GET" && request.key.is_none())
+			.count();
+
+		assert_eq!(client.list_prefix("a").await.unwrap(), vec![ "a1" ]);
+		assert_eq!(client.list_prefix("a").await.unwrap(), vec![ "a1" ]);
+		assert_eq!(client.list_prefix("b").await.unwrap(), vec![ "b1" ]);
+		assert_eq!(listings(), 2);
+
+		client.set("a2", "2").await.unwrap();
+		assert_eq!(client.list_prefix("a").await.unwrap(), vec![ "a1", "a2" ]);
+		assert_eq!(client.list_prefix("b").await.unwrap(), vec![ "b1" ]);
+		assert_eq!(listings(), 3);
+	}
+
+	#[tokio::test]
+	async fn request_id_header_is_sent_when_enabled() {
+		let server = MockServer::start();
+		Client::new_url(server.url()).get("a").await.unwrap();
+		let client = Client::new_url(server.url()).request_id_header(true);
+		client.get("a").await.unwrap();
+		client.get("a").await.unwrap();
+
+		let requests = server.requests();
+		assert!(requests[0].header(REQUEST_ID_HEADER).is_none());
+		let first = requests[1].header(REQUEST_ID_HEADER).unwrap();
+		let second = requests[2].header(REQUEST_ID_HEADER).unwrap();
+		assert!(first.starts_with(&format!("{}-", std::process::id())));
+		assert_ne!(first, second);
+	}
+
+	#[tokio::test]
+	async fn retried_writes_reuse_their_idempotency_key() {
+		let server = MockServer::with_handler(|_, state| {
+			if state.requests.len() == 1 {
+				Some(MockResponse::status(429).header("Retry-After", "0"))
+			} else {
+				None
+			}
+		});
+		let client = Client::new_url(server.url()).idempotency_keys(true).retries(1);
+		client.set("a", "1").await.unwrap();
+		client.set("b", "2").await.unwrap();
+
+		let requests = server.requests();
+		let keys: Vec<_> = requests.iter()
+			.map(|request| request.header(IDEMPOTENCY_KEY_HEADER).unwrap())
+			.collect();
+		assert_eq!(keys.len(), 3);
+		assert_eq!(keys[0], keys[1]);
+		assert_ne!(keys[1], keys[2]);
+		assert_eq!(server.values()["a"], "1");
+	}
+
+	#[tokio::test]
+	async fn set_timeout_limits_each_request() {
+		let server = MockServer::start();
+		server.set_delay(Duration::from_millis(500));
+		let mut client = Client::new_url(server.url());
+
+		client.set_timeout(Some(Duration::from_millis(100))).unwrap();
+		assert!(matches!(client.get("a").await, Err(Error::Http(err)) if err.is_timeout()));
+
+		client.set_timeout(None).unwrap();
+		assert!(client.get("a").await.unwrap().is_none());
+	}
+
+	#[tokio::test]
+	async fn accept_header_is_sent_with_reads() {
+		let server = MockServer::start();
+		Client::new_url(server.url()).accept("text/plain").get("a").await.unwrap();
+		assert_eq!(server.requests()[0].header("Accept"), Some("text/plain"));
+	}
+
+	#[tokio::test]
+	async fn bearer_token_is_sent_with_every_request() {
+		let server = MockServer::start();
+		let client = Client::new_url(server.url()).bearer_token("secret");
+		client.set("a", "1").await.unwrap();
+		client.get("a").await.unwrap();
+		client.list_prefix("").await.unwrap();
+
+		let requests = server.requests();
+		assert_eq!(requests.len(), 3);
+		for request in &requests {
+			assert_eq!(request.header("Authorization"), Some("Bearer secret"));
+		}
+	}
+
+	#[tokio::test]
+	async fn inspect_receives_request_and_response_info() {
+		let server = MockServer::start();
+		let seen = Arc::new(std::sync::Mutex::new(Vec::new()));
+		let hook_seen = seen.clone();
+		let client = Client::new_url(server.url()).inspect(move |request, response| {
+			hook_seen.lock().unwrap().push((request.clone(), response.clone()));
+		});
+		client.set("a", "hello").await.unwrap();
+		client.get("a").await.unwrap();
+		client.get("missing").await.unwrap();
+
+		let seen = seen.lock().unwrap();
+		let summary: Vec<_> = seen.iter()
+			.map(|(request, response)| (
+				request.method.as_str(),
+				request.key.as_deref(),
+				request.body_len,
+				response.status,
+				response.content_length,
+			))
+			.collect();
+		assert_eq!(summary, vec![
+			("POST", Some("a"), "a=hello".len(), Some(200), Some(0)),
+			("GET", Some("a"), 0, Some(200), Some(5)),
+			("GET", Some("missing"), 0, Some(404), Some(0)),
+		]);
+	}
+
+	#[tokio::test]
+	async fn get_with_modified_parses_last_modified() {
+		let server = MockServer::with_handler(|request, _| match request.key.as_deref() {
+			Some("dated") => Some(MockResponse::status(200)
+				.header("Last-Modified", "Sun, 06 Nov 1994 08:49:37 GMT")
+				.body("1")),
+			_ => None,
+		});
+		server.insert("undated", "2");
+		let client = Client::new_url(server.url());
+
+		let (value, modified) = client.get_with_modified("dated").await.unwrap().unwrap();
+		assert_eq!(value, "1");
+		assert_eq!(modified, Some(UNIX_EPOCH + Duration::from_secs(784_111_777)));
+		let undated = client.get_with_modified("undated").await.unwrap();
+		assert_eq!(undated, Some(("2".to_string(), None)));
+		assert_eq!(client.get_with_modified("missing").await.unwrap(), None);
+	}
+
+	#[tokio::test]
+	async fn empty_values_are_distinct_from_missing_keys() {
+		let server = MockServer::start();
+		server.insert("empty", "");
+		let client = Client::new_url(server.url());
+
+		assert_eq!(client.get("empty").await.unwrap().as_deref(), Some(""));
+		assert_eq!(client.get("missing").await.unwrap(), None);
+	}
+
+	#[tokio::test]
+	async fn get_all_as_maps_each_value() {
+		let server = MockServer::start();
+		server.insert("a", "1");
+		server.insert("b", "-20");
+		let client = Client::new_url(server.url());
+
+		let values = client.get_all_as(|value| value.parse::<i64>().unwrap()).await.unwrap();
+		assert_eq!(values.len(), 2);
+		assert_eq!(values["a"], 1);
+		assert_eq!(values["b"], -20);
+	}
+
+	#[tokio::test]
+	async fn empty_except_keeps_the_listed_keys() {
+		let server = MockServer::start();
+		for key in &[ "a", "b", "c", "d" ] {
+			server.insert(key, "1");
+		}
+		let client = Client::new_url(server.url());
+
+		let keep: HashSet<String> = vec![ "b".to_string(), "d".to_string() ].into_iter().collect();
+		assert_eq!(client.empty_except(&keep).await.unwrap(), 2);
+		assert_eq!(server.values().keys().collect::<Vec<_>>(), vec![ "b", "d" ]);
+	}
+
+	#[tokio::test]
+	async fn list_matching_filters_by_prefix_and_suffix() {
+		let server = MockServer::start();
+		for key in &[ "user:1.json", "user:2.txt", "user:3.json", "post:4.json" ] {
+			server.insert(key, "1");
+		}
+		let client = Client::new_url(server.url());
+
+		let keys = client.list_matching("user:", ".json").await.unwrap();
+		assert_eq!(keys, vec![ "user:1.json", "user:3.json" ]);
+	}
+
+	#[tokio::test]
+	async fn clone_with_url_keeps_options() {
+		let first = MockServer::start();
+		let second = MockServer::start();
+		let client = Client::new_url(first.url()).bearer_token("secret");
+
+		client.clone_with_url(second.url()).set("a", "1").await.unwrap();
+		assert!(first.requests().is_empty());
+		assert_eq!(second.values()["a"], "1");
+		assert_eq!(second.requests()[0].header("Authorization"), Some("Bearer secret"));
+	}
+
+	#[tokio::test]
+	async fn increment_float_adds_to_the_current_value() {
+		let server = MockServer::start();
+		server.insert("existing", "1.5");
+		server.insert("text", "abc");
+		let client = Client::new_url(server.url());
+
+		assert_eq!(client.increment_float("new", 0.25).await.unwrap(), 0.25);
+		assert_eq!(client.increment_float("existing", 0.25).await.unwrap(), 1.75);
+		assert_eq!(server.values()["new"], "0.25");
+		assert_eq!(server.values()["existing"], "1.75");
+		let result = client.increment_float("text", 1.0).await;
+		assert!(matches!(result, Err(Error::Parse(value)) if value == "abc"));
+		assert_eq!(server.values()["text"], "abc");
+	}
+
+	#[tokio::test]
+	async fn trim_values_strips_surrounding_whitespace() {
+		let server = MockServer::start();
+		server.insert("a", "value\n");
+
+		let client = Client::new_url(server.url());
+		assert_eq!(client.get("a").await.unwrap().as_deref(), Some("value\n"));
+		let client = client.trim_values(true);
+		assert_eq!(client.get("a").await.unwrap().as_deref(), Some("value"));
+	}
+
+	#[tokio::test]
+	async fn get_first_stops_at_the_first_present_key() {
+		let server = MockServer::start();
+		server.insert("b", "2");
+		server.insert("c", "3");
+		let client = Client::new_url(server.url());
+
+		let first = client.get_first(&[ "a", "b", "c" ]).await.unwrap();
+		assert_eq!(first, Some(("b".to_string(), "2".to_string())));
+		let keys: Vec<_> = server.requests().into_iter()
+			.map(|request| request.key.unwrap())
+			.collect();
+		assert_eq!(keys, vec![ "a", "b" ]);
+	}
+
+	#[tokio::test]
+	async fn exists_many_reports_each_key() {
+		let server = MockServer::start();
+		server.insert("a", "1");
+		server.insert("c", "");
+		let client = Client::new_url(server.url());
+
+		let exists = client.exists_many(vec![ "a", "b", "c" ]).await.unwrap();
+		assert_eq!(exists.len(), 3);
+		assert!(exists["a"]);
+		assert!(!exists["b"]);
+		assert!(exists["c"]);
+	}
+
+	#[tokio::test]
+	async fn get_all_with_progress_reports_each_fetch() {
+		let server = MockServer::start();
+		for key in &[ "a", "b", "c" ] {
+			server.insert(key, "1");
+		}
+		let client = Client::new_url(server.url());
+
+		let calls = std::cell::RefCell::new(Vec::new());
+		let progress = |fetched, total| calls.borrow_mut().push((fetched, total));
+		let values = client.get_all_with_progress(progress).await.unwrap();
+		assert_eq!(values.len(), 3);
+		assert_eq!(calls.into_inner(), vec![ (0, 3), (1, 3), (2, 3), (3, 3) ]);
+	}
+
+	#[tokio::test]
+	async fn set_display_stores_the_formatted_value() {
+		struct Point(i32, i32);
+
+		impl Display for Point {
+			fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+				write!(f, "({}, {})", self.0, self.1)
+			}
+		}
+
+		let server = MockServer::start();
+		let client = Client::new_url(server.url());
+		client.set_display("count", 42).await.unwrap();
+		client.set_display("point", Point(1, -2)).await.unwrap();
+
+		let values = server.values();
+		assert_eq!(values["count"], "42");
+		assert_eq!(values["point"], "(1, -2)");
+	}
+
+	#[tokio::test]
+	async fn delete_many_sends_one_delete_per_key() {
+		let server = MockServer::start();
+		for key in &[ "a", "b", "c" ] {
+			server.insert(key, "1");
+		}
+		let client = Client::new_url(server.url());
+
+		client.delete_many(vec![ "a", "c" ]).await.unwrap();
+		assert_eq!(server.values().keys().collect::<Vec<_>>(), vec![ "b" ]);
+		let mut deleted: Vec<_> = server.requests().into_iter()
+			.filter(|request| request.method == "DELETE")
+			.map(|request| request.key.unwrap())
+			.collect();
+		deleted.sort();
+		assert_eq!(deleted, vec![ "a", "c" ]);
+	}
+
+	#[tokio::test]
+	async fn list_prefix_limited_returns_at_most_limit_keys() {
+		let server = MockServer::start();
+		for key in &[ "a1", "a2", "a3", "b1" ] {
+			server.insert(key, "1");
+		}
+		let client = Client::new_url(server.url());
+
+		assert_eq!(client.list_prefix_limited("a", 2).await.unwrap(), vec![ "a1", "a2" ]);
+		assert_eq!(client.list_prefix_limited("a", 10).await.unwrap(), vec![ "a1", "a2", "a3" ]);
+		assert!(client.list_prefix_limited("a", 0).await.unwrap().is_empty());
+	}
+
+	#[tokio::test]
+	async fn put_sets_or_deletes() {
+		let server = MockServer::start();
+		server.insert("b", "old");
+		let client = Client::new_url(server.url());
+
+		client.put("a", Some("1")).await.unwrap();
+		client.put("b", None::<String>).await.unwrap();
+		let values = server.values();
+		assert_eq!(values.len(), 1);
+		assert_eq!(values["a"], "1");
 	}
 }
@@ -0,0 +1,47 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{ Duration, Instant };
+
+/// A cache of key listings, keyed by prefix.
+///
+/// Entries expire after the TTL. Writing or deleting a key evicts every entry whose prefix
+/// the key starts with, since those listings may no longer be accurate.
+#[derive(Debug)]
+pub(crate) struct ListCache {
+	ttl: Duration,
+	entries: Mutex<HashMap<String, (Instant, Vec<String>)>>,
+}
+
+impl ListCache {
+	pub(crate) fn new(ttl: Duration) -> Self {
+		ListCache {
+			ttl,
+			entries: Mutex::new(HashMap::new()),
+		}
+	}
+
+	pub(crate) fn ttl(&self) -> Duration {
+		self.ttl
+	}
+
+	pub(crate) fn get(&self, prefix: &str) -> Option<Vec<String>> {
+		let mut entries = self.entries.lock().unwrap();
+
+		match entries.get(prefix) {
+			Some((inserted, keys)) if inserted.elapsed() < self.ttl => Some(keys.clone()),
+			Some(_) => {
+				entries.remove(prefix);
+				None
+			},
+			None => None,
+		}
+	}
+
+	pub(crate) fn insert(&self, prefix: String, keys: Vec<String>) {
+		self.entries.lock().unwrap().insert(prefix, (Instant::now(), keys));
+	}
+
+	pub(crate) fn invalidate(&self, key: &str) {
+		self.entries.lock().unwrap().retain(|prefix, _| !key.starts_with(prefix.as_str()));
+	}
+}
@@ -0,0 +1,102 @@
+use crate::{ Result, SyncClient };
+
+/// A map-like view of the database.
+///
+/// Provides methods named after those of [`HashMap`](std::collections::HashMap) which delegate
+/// to a [`SyncClient`]. Every call makes at least one request to the database, and unlike a
+/// `HashMap`, [`insert`](Self::insert) and [`remove`](Self::remove) do not return the previous
+/// value, since fetching it would take another request.
+///
+/// ```no_run
+/// # fn main() -> replitdb::Result<()> {
+/// let map = replitdb::DbMap::new(replitdb::SyncClient::new());
+/// map.insert("greeting", "hello world")?;
+/// assert!(map.contains_key("greeting")?);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct DbMap {
+	client: SyncClient,
+}
+
+impl DbMap {
+	/// Create a new view of the database the client is connected to.
+	pub fn new(client: SyncClient) -> Self {
+		DbMap { client }
+	}
+
+	/// Get the value of the specified key. Returns `Ok(None)` if the key does not exist.
+	pub fn get(&self, key: impl Into<String>) -> Result<Option<String>> {
+		self.client.get(key)
+	}
+
+	/// Check whether the specified key exists.
+	pub fn contains_key(&self, key: impl Into<String>) -> Result<bool> {
+		self.client.exists(key)
+	}
+
+	/// Set the value of the specified key.
+	pub fn insert(&self, key: impl Into<String>, value: impl Into<String>) -> Result<()> {
+		self.client.set(key, value)
+	}
+
+	/// Delete the specified key.
+	pub fn remove(&self, key: impl Into<String>) -> Result<()> {
+		self.client.delete(key)
+	}
+
+	/// List all keys.
+	pub fn keys(&self) -> Result<Vec<String>> {
+		self.client.list()
+	}
+
+	/// Count all keys.
+	pub fn len(&self) -> Result<usize> {
+		Ok(self.client.list()?.len())
+	}
+
+	/// Check whether there are no keys.
+	pub fn is_empty(&self) -> Result<bool> {
+		Ok(self.len()? == 0)
+	}
+
+	/// Get the client this view delegates to.
+	pub fn client(&self) -> &SyncClient {
+		&self.client
+	}
+}
+
+impl From<SyncClient> for DbMap {
+	fn from(client: SyncClient) -> Self {
+		Self::new(client)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::mock::MockServer;
+
+	#[test]
+	fn behaves_like_a_map() {
+		let server = MockServer::start();
+		let map = DbMap::new(SyncClient::new_url(server.url()));
+
+		assert!(map.is_empty().unwrap());
+		assert_eq!(map.get("a").unwrap(), None);
+
+		map.insert("a", "1").unwrap();
+		map.insert("b", "2").unwrap();
+		assert!(map.contains_key("a").unwrap());
+		assert_eq!(map.get("a").unwrap().as_deref(), Some("1"));
+		assert_eq!(map.keys().unwrap(), vec![ "a", "b" ]);
+		assert_eq!(map.len().unwrap(), 2);
+
+		map.remove("a").unwrap();
+		assert!(!map.contains_key("a").unwrap());
+		assert_eq!(map.len().unwrap(), 1);
+		assert!(!map.is_empty().unwrap());
+		assert_eq!(server.values().keys().collect::<Vec<&String>>(), vec![ "b" ]);
+	}
+}
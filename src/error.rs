@@ -0,0 +1,131 @@
+use std::fmt;
+use std::string::FromUtf8Error;
+
+/// An error returned by a client.
+#[derive(Debug)]
+pub enum Error {
+	/// The HTTP request failed, or its response could not be read.
+	Http(reqwest::Error),
+	/// The server responded with an unsuccessful status code.
+	Server {
+		status: u16,
+		body: String,
+	},
+	/// A key or value returned by the server could not be decoded.
+	Decode(FromUtf8Error),
+	/// A filesystem operation failed.
+	Io(std::io::Error),
+	/// The database URL is malformed.
+	InvalidUrl(url::ParseError),
+	/// The `REPLIT_DB_URL` environment variable is not set or is not valid Unicode.
+	MissingEnvVar(std::env::VarError),
+	/// The key starts with a prefix reserved for internal use.
+	ReservedKey(String),
+	/// The key is empty, `.` or `..`, or contains a newline, so it could not be read or listed
+	/// correctly.
+	InvalidKey(String),
+	/// The key does not exist.
+	MissingKey(String),
+	/// A destructive operation was not given the required confirmation.
+	ConfirmationRequired,
+	/// A value is larger than the configured read limit.
+	ValueTooLarge {
+		limit: u64,
+	},
+	/// A stored value could not be parsed as the requested type.
+	Parse(String),
+	/// The total size of the requested values exceeds the configured limit.
+	TooLarge {
+		size: u64,
+		limit: u64,
+	},
+	/// A response, or part of its body, was not received within the read timeout.
+	Timeout,
+	/// A conditional write was rejected because the key was modified since the given time.
+	PreconditionFailed,
+	/// Some keys could not be deleted by a best-effort bulk delete, listed with the error for each.
+	DeleteFailed(Vec<(String, Error)>),
+	/// A value could not be serialized to JSON, or a stored value could not be deserialized.
+	#[cfg(feature = "json")]
+	Json(serde_json::Error),
+}
+
+impl fmt::Display for Error {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Error::Http(err) => write!(f, "request failed: {}", err),
+			Error::Server { status, body } => write!(f, "server responded with {}: {}", status, body),
+			Error::Decode(err) => write!(f, "failed to decode response: {}", err),
+			Error::Io(err) => write!(f, "filesystem operation failed: {}", err),
+			Error::InvalidUrl(err) => write!(f, "invalid database URL: {}", err),
+			Error::MissingEnvVar(err) => write!(f, "failed to read REPLIT_DB_URL: {}", err),
+			Error::ReservedKey(key) => write!(f, "key uses a reserved prefix: {}", key),
+			Error::InvalidKey(key) => write!(f, "key is empty, a dot segment or contains a newline: {:?}", key),
+			Error::MissingKey(key) => write!(f, "key does not exist: {}", key),
+			Error::ConfirmationRequired => write!(f, "operation requires confirmation"),
+			Error::ValueTooLarge { limit } => write!(f, "value exceeds the read limit of {} bytes", limit),
+			Error::Parse(value) => write!(f, "failed to parse value: {:?}", value),
+			Error::TooLarge { size, limit } => write!(f, "values total at least {} bytes, exceeding the limit of {}", size, limit),
+			Error::Timeout => write!(f, "read timed out"),
+			Error::PreconditionFailed => write!(f, "key was modified since the given time"),
+			Error::DeleteFailed(failed) => {
+				let keys = failed.iter().map(|(key, _)| key.as_str()).collect::<Vec<&str>>();
+				write!(f, "failed to delete {} keys: {}", failed.len(), keys.join(", "))
+			},
+			#[cfg(feature = "json")]
+			Error::Json(err) => write!(f, "failed to convert JSON: {}", err),
+		}
+	}
+}
+
+impl std::error::Error for Error {
+	fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+		match self {
+			Error::Http(err) => Some(err),
+			Error::Decode(err) => Some(err),
+			Error::Io(err) => Some(err),
+			Error::InvalidUrl(err) => Some(err),
+			Error::MissingEnvVar(err) => Some(err),
+			#[cfg(feature = "json")]
+			Error::Json(err) => Some(err),
+			_ => None,
+		}
+	}
+}
+
+impl From<reqwest::Error> for Error {
+	fn from(err: reqwest::Error) -> Self {
+		Error::Http(err)
+	}
+}
+
+impl From<FromUtf8Error> for Error {
+	fn from(err: FromUtf8Error) -> Self {
+		Error::Decode(err)
+	}
+}
+
+impl From<std::io::Error> for Error {
+	fn from(err: std::io::Error) -> Self {
+		Error::Io(err)
+	}
+}
+
+impl From<url::ParseError> for Error {
+	fn from(err: url::ParseError) -> Self {
+		Error::InvalidUrl(err)
+	}
+}
+
+impl From<std::env::VarError> for Error {
+	fn from(err: std::env::VarError) -> Self {
+		Error::MissingEnvVar(err)
+	}
+}
+
+#[cfg(feature = "json")]
+impl From<serde_json::Error> for Error {
+	fn from(err: serde_json::Error) -> Self {
+		Error::Json(err)
+	}
+}
@@ -4,14 +4,563 @@
 //!
 //! [the NodeJS client]: https://www.npmjs.com/package/@replit/database
 
+use std::cmp::Ordering as CmpOrdering;
+use std::collections::{ BTreeSet, HashMap };
+use std::fmt;
+use std::sync::Arc;
+use std::sync::atomic::{ AtomicU64, Ordering };
+use std::time::{ Duration, SystemTime, UNIX_EPOCH };
+
+use reqwest::Method;
+use reqwest::header::{ HeaderMap, CONTENT_LENGTH, RETRY_AFTER };
+
+use sha2::{ Digest, Sha256 };
+
 mod async_client;
+#[cfg(feature = "cache")]
+mod cache;
+mod db_map;
+mod error;
+mod mirror_client;
+#[cfg(test)]
+mod mock;
 mod sync_client;
 
 pub use async_client::Client as AsyncClient;
+pub use db_map::DbMap;
+pub use error::Error;
+pub use mirror_client::MirrorClient;
 pub use sync_client::Client as SyncClient;
 
+/// A result whose error type is [`Error`].
+///
+/// ```
+/// fn greeting(client: &replitdb::SyncClient) -> replitdb::Result<String> {
+///     Ok(client.get("greeting")?.unwrap_or_default())
+/// }
+/// ```
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// The confirmation string required by `empty_confirmed` on either client.
+pub const EMPTY_CONFIRMATION: &str = "DELETE ALL";
+
 const URL_VAR: &str = "REPLIT_DB_URL";
+const CHECK_KEY: &str = "__replitdb_check__";
+const REQUEST_ID_HEADER: &str = "X-Request-Id";
+const IDEMPOTENCY_KEY_HEADER: &str = "Idempotency-Key";
+const RESERVED_PREFIXES: &[&str] = &[ "__meta__:", "__index__:", HASHED_KEY_PREFIX, EXPIRY_PREFIX ];
+const HASHED_KEY_PREFIX: &str = "__hash__:";
+const EXPIRY_PREFIX: &str = "__exp__:";
+const MAX_KEY_LEN: usize = 1000;
+const DEFAULT_RETRY_BACKOFF: Duration = Duration::from_millis(100);
+
+static REQUEST_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// The outcome of a connection check, as returned by `check` on either client.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CheckResult {
+	/// Whether the server responded at all.
+	pub reachable: bool,
+	/// Whether a read succeeded, showing that the server accepted the database URL's credentials.
+	pub authenticated: bool,
+	/// Whether a temporary key could be written.
+	pub writable: bool,
+}
+
+/// A value which is either JSON or plain text, as returned by `get_dynamic` on either client.
+#[cfg(feature = "serde")]
+#[derive(Clone, Debug, PartialEq)]
+pub enum DbValue {
+	/// The value parsed as JSON.
+	Json(serde_json::Value),
+	/// The value could not be parsed as JSON.
+	Text(String),
+}
+
+/// The differences between two snapshots of the database, as yielded by
+/// [`AsyncClient::changes`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Changes {
+	/// Keys that were added, with their values.
+	pub added: HashMap<String, String>,
+	/// Keys that were removed, with their last known values.
+	pub removed: HashMap<String, String>,
+	/// Keys whose values changed, with their new values.
+	pub modified: HashMap<String, String>,
+}
+
+impl Changes {
+	fn between(old: &HashMap<String, String>, new: &HashMap<String, String>) -> Self {
+		let mut changes = Changes::default();
+
+		for (key, value) in new {
+			match old.get(key) {
+				None => {
+					changes.added.insert(key.clone(), value.clone());
+				},
+				Some(old_value) if old_value != value => {
+					changes.modified.insert(key.clone(), value.clone());
+				},
+				Some(_) => {},
+			}
+		}
+
+		for (key, value) in old {
+			if !new.contains_key(key) {
+				changes.removed.insert(key.clone(), value.clone());
+			}
+		}
+
+		changes
+	}
+
+	/// Whether there are no changes.
+	pub fn is_empty(&self) -> bool {
+		self.added.is_empty() && self.removed.is_empty() && self.modified.is_empty()
+	}
+}
+
+/// Information about a request, passed to an [`inspect`](SyncClient::inspect) hook.
+#[derive(Clone, Debug)]
+pub struct RequestInfo {
+	/// The HTTP method of the request.
+	pub method: Method,
+	/// The key the request operates on, if it targets a single key.
+	pub key: Option<String>,
+	/// The length of the request body in bytes.
+	pub body_len: usize,
+}
+
+/// Information about a response, passed to an [`inspect`](SyncClient::inspect) hook.
+#[derive(Clone, Debug)]
+pub struct ResponseInfo {
+	/// The status code of the response, or `None` if the request failed.
+	pub status: Option<u16>,
+	/// The length of the response body in bytes, if known.
+	pub content_length: Option<u64>,
+	/// The time taken to receive the response headers.
+	pub elapsed: Duration,
+}
+
+type InspectFn = dyn Fn(&RequestInfo, &ResponseInfo) + Send + Sync;
+type KeyFn = dyn Fn(&str) -> String + Send + Sync;
+
+struct Hook<F: ?Sized>(Arc<F>);
+
+impl<F: ?Sized> Clone for Hook<F> {
+	fn clone(&self) -> Self {
+		Hook(self.0.clone())
+	}
+}
+
+impl<F: ?Sized> fmt::Debug for Hook<F> {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.write_str("Hook")
+	}
+}
+
+#[derive(Clone, Debug, Default)]
+struct Options {
+	accept: Option<String>,
+	bearer_token: Option<String>,
+	request_id_header: bool,
+	idempotency_keys: bool,
+	best_effort_deletes: bool,
+	allow_reserved_keys: bool,
+	hash_long_keys: bool,
+	trim_values: bool,
+	max_total_bytes: Option<u64>,
+	max_value_read_bytes: Option<u64>,
+	set_many_chunk_bytes: Option<usize>,
+	timeout: Option<Duration>,
+	connect_timeout: Option<Duration>,
+	read_timeout: Option<Duration>,
+	retries: u32,
+	retry_backoff: Option<Duration>,
+	inspect: Option<Hook<InspectFn>>,
+	key_transform: Option<(Hook<KeyFn>, Hook<KeyFn>)>,
+	key_prefix: Option<String>,
+	max_concurrency: Option<usize>,
+	#[cfg(feature = "cache")]
+	list_cache: Option<Arc<cache::ListCache>>,
+}
+
+impl Options {
+	fn check_reserved(&self, key: &str) -> Result<()> {
+		if !self.allow_reserved_keys && RESERVED_PREFIXES.iter().any(|prefix| key.starts_with(prefix)) {
+			Err(Error::ReservedKey(key.to_string()))
+		} else {
+			Ok(())
+		}
+	}
+
+	fn storage_key(&self, key: &str) -> Result<String> {
+		if key.is_empty() || key.contains('\n') {
+			return Err(Error::InvalidKey(key.to_string()));
+		}
+
+		let mut storage_key = self.transform_key(key);
+
+		// Hash before transforming, so hashed keys stay inside the transform's namespace
+		if self.hash_long_keys && storage_key.len() > MAX_KEY_LEN {
+			let hashed = format!("{}{:x}", HASHED_KEY_PREFIX, Sha256::digest(key.as_bytes()));
+			storage_key = self.transform_key(&hashed);
+		}
+
+		if storage_key == "." || storage_key == ".." {
+			// URLs treat these as dot segments, so they would address the database itself
+			Err(Error::InvalidKey(key.to_string()))
+		} else {
+			Ok(storage_key)
+		}
+	}
+
+	fn transform_key(&self, key: &str) -> String {
+		let key = match &self.key_transform {
+			Some((transform, _)) => (transform.0)(key),
+			None => key.to_string(),
+		};
+
+		match &self.key_prefix {
+			Some(prefix) => format!("{}{}", prefix, key),
+			None => key,
+		}
+	}
+
+	fn untransform_key(&self, key: String) -> String {
+		let key = match &self.key_prefix {
+			Some(prefix) => match key.strip_prefix(prefix.as_str()) {
+				Some(key) => key.to_string(),
+				None => key,
+			},
+			None => key,
+		};
+
+		match &self.key_transform {
+			Some((_, inverse)) => (inverse.0)(&key),
+			None => key,
+		}
+	}
+
+	fn retry_delay(&self, attempt: u32) -> Duration {
+		self.retry_backoff
+			.unwrap_or(DEFAULT_RETRY_BACKOFF)
+			.saturating_mul(2u32.saturating_pow(attempt))
+	}
+
+	fn trim(&self, value: String) -> String {
+		if self.trim_values {
+			value.trim().to_string()
+		} else {
+			value
+		}
+	}
+
+	fn without_caches(&self) -> Self {
+		Options {
+			#[cfg(feature = "cache")]
+			list_cache: self.list_cache.as_ref()
+				.map(|cache| Arc::new(cache::ListCache::new(cache.ttl()))),
+			..self.clone()
+		}
+	}
+
+	fn invalidate(&self, _key: &str) {
+		#[cfg(feature = "cache")]
+		if let Some(cache) = &self.list_cache {
+			cache.invalidate(_key);
+		}
+	}
+}
+
+fn get_url_from_env() -> std::result::Result<String, std::env::VarError> {
+	std::env::var(URL_VAR)
+}
+
+fn next_request_id() -> String {
+	format!("{}-{}", std::process::id(), REQUEST_COUNTER.fetch_add(1, Ordering::Relaxed))
+}
+
+fn next_idempotency_key() -> String {
+	let now = SystemTime::now()
+		.duration_since(UNIX_EPOCH)
+		.unwrap_or_default()
+		.as_nanos();
+
+	format!("{:x}-{}", now, next_request_id())
+}
+
+fn unix_seconds(time: SystemTime) -> u64 {
+	time.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs()
+}
+
+fn expiry_index_key(key: &str, expires: u64) -> String {
+	// The key itself is stored as the index entry's value, so hash it to keep the index key short
+	format!("{}{:020}:{:x}", EXPIRY_PREFIX, expires, Sha256::digest(key.as_bytes()))
+}
+
+fn parse_expiry_index_key(index_key: &str) -> Option<(u64, &str)> {
+	let (expires, hash) = index_key.strip_prefix(EXPIRY_PREFIX)?.split_once(':')?;
+
+	Some((expires.parse().ok()?, hash))
+}
+
+fn is_transient(err: &Error, idempotent: bool) -> bool {
+	match err {
+		Error::Http(err) => err.is_connect() || (idempotent && (err.is_timeout() || err.is_request())),
+		Error::Timeout => idempotent,
+		_ => false,
+	}
+}
+
+fn checksum_pairs(pairs: &HashMap<String, String>) -> u64 {
+	let mut keys = pairs.keys().collect::<Vec<&String>>();
+	keys.sort();
+
+	let mut hasher = Sha256::new();
+	for key in keys {
+		let value = &pairs[key];
+
+		hasher.update((key.len() as u64).to_be_bytes());
+		hasher.update(key.as_bytes());
+		hasher.update((value.len() as u64).to_be_bytes());
+		hasher.update(value.as_bytes());
+	}
+
+	let digest = hasher.finalize();
+	let mut bytes = [0; 8];
+	bytes.copy_from_slice(&digest[..8]);
+
+	u64::from_be_bytes(bytes)
+}
+
+fn content_length(headers: &HeaderMap) -> Option<u64> {
+	headers.get(CONTENT_LENGTH)?
+		.to_str()
+		.ok()?
+		.parse()
+		.ok()
+}
+
+fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+	let value = headers.get(RETRY_AFTER)?.to_str().ok()?.trim();
+
+	match value.parse::<u64>() {
+		Ok(seconds) => Some(Duration::from_secs(seconds)),
+		Err(_) => {
+			let date = httpdate::parse_http_date(value).ok()?;
+			Some(date.duration_since(SystemTime::now()).unwrap_or_default())
+		},
+	}
+}
+
+fn key_to_file_name(key: &str) -> String {
+	let name = urlencoding::encode(key).into_owned();
+
+	// `encode` leaves dots alone, so escape names that would refer to a directory
+	if name == "." || name == ".." {
+		name.replace('.', "%2E")
+	} else {
+		name
+	}
+}
+
+fn file_name_to_key(name: &str) -> Result<String> {
+	Ok(urlencoding::decode(name)?.into_owned())
+}
+
+fn collapse_to_depth(
+	prefix: &str,
+	keys: Vec<String>,
+	separator: char,
+	max_depth: usize,
+) -> Vec<String> {
+	let paths = keys.iter()
+		.map(|key| {
+			let segments = key.strip_prefix(prefix)
+				.unwrap_or(key)
+				.split(separator)
+				.take(max_depth)
+				.collect::<Vec<&str>>();
+
+			format!("{}{}", prefix, segments.join(&separator.to_string()))
+		})
+		.collect::<BTreeSet<String>>();
+
+	paths.into_iter().collect()
+}
+
+fn compare_values(a: &str, b: &str) -> CmpOrdering {
+	// NaN is treated as text, as it has no place in a total numeric order.
+	let number = |value: &str| value.parse::<f64>().ok().filter(|value| !value.is_nan());
+
+	match (number(a), number(b)) {
+		(Some(a), Some(b)) => a.partial_cmp(&b).unwrap(),
+		(Some(_), None) => CmpOrdering::Less,
+		(None, Some(_)) => CmpOrdering::Greater,
+		(None, None) => a.cmp(b),
+	}
+}
+
+fn dedup_pairs<K: Into<String>, V: Into<String>>(
+	pairs: impl IntoIterator<Item = (K, V)>,
+) -> Vec<(String, String)> {
+	let mut out: Vec<(String, String)> = Vec::new();
+	let mut indices: HashMap<String, usize> = HashMap::new();
+
+	for (key, value) in pairs {
+		let key: String = key.into();
+
+		match indices.get(&key) {
+			Some(&index) => out[index].1 = value.into(),
+			None => {
+				indices.insert(key.clone(), out.len());
+				out.push((key, value.into()));
+			},
+		}
+	}
+
+	out
+}
+
+fn chunk_fields(fields: Vec<String>, limit: Option<usize>) -> Vec<String> {
+	let limit = match limit {
+		Some(limit) => limit,
+		None => return vec![ fields.join("&") ],
+	};
+
+	let mut chunks = Vec::new();
+	let mut chunk = String::new();
+
+	for field in fields {
+		if !chunk.is_empty() && chunk.len() + 1 + field.len() > limit {
+			chunks.push(std::mem::take(&mut chunk));
+		}
+
+		if !chunk.is_empty() {
+			chunk.push('&');
+		}
+		chunk.push_str(&field);
+	}
+
+	if !chunk.is_empty() {
+		chunks.push(chunk);
+	}
+
+	chunks
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn strings(values: &[&str]) -> Vec<String> {
+		values.iter().map(|value| value.to_string()).collect()
+	}
+
+	#[test]
+	fn collapse_to_depth_keeps_requested_depth() {
+		let keys = strings(&[ "a:b:c", "a:b:d", "a:e", "a:f:g:h" ]);
+
+		assert_eq!(collapse_to_depth("a:", keys.clone(), ':', 1), strings(&[ "a:b", "a:e", "a:f" ]));
+		assert_eq!(collapse_to_depth("a:", keys, ':', 2), strings(&[ "a:b:c", "a:b:d", "a:e", "a:f:g" ]));
+	}
+
+	#[test]
+	fn dedup_pairs_keeps_last_value() {
+		let pairs = dedup_pairs(vec![ ("a", "1"), ("b", "2"), ("a", "3") ]);
+
+		assert_eq!(pairs, vec![ ("a".to_string(), "3".to_string()), ("b".to_string(), "2".to_string()) ]);
+	}
+
+	#[test]
+	fn compare_values_is_a_total_order() {
+		let mut values = vec![ "b", "NaN", "10", "nan", "-1.5", "a", "2", "inf" ];
+		values.sort_by(|a, b| compare_values(a, b));
+
+		assert_eq!(values, vec![ "-1.5", "2", "10", "inf", "NaN", "a", "b", "nan" ]);
+	}
+
+	#[test]
+	fn retry_after_reads_seconds_and_dates() {
+		let mut headers = HeaderMap::new();
+		assert_eq!(retry_after(&headers), None);
+
+		headers.insert(RETRY_AFTER, "3".parse().unwrap());
+		assert_eq!(retry_after(&headers), Some(Duration::from_secs(3)));
+
+		headers.insert(RETRY_AFTER, "Wed, 21 Oct 2015 07:28:00 GMT".parse().unwrap());
+		assert_eq!(retry_after(&headers), Some(Duration::ZERO));
+
+		headers.insert(RETRY_AFTER, "soon".parse().unwrap());
+		assert_eq!(retry_after(&headers), None);
+	}
+
+	#[test]
+	fn expiry_index_key_round_trips() {
+		let long_key = "k".repeat(2000);
+
+		for &key in &[ "a", "a:b", "\u{1F600} key", long_key.as_str() ] {
+			let index_key = expiry_index_key(key, 1_700_000_000);
+			let (expires, hash) = parse_expiry_index_key(&index_key).unwrap();
+
+			assert!(index_key.len() < 100);
+			assert_eq!(expires, 1_700_000_000);
+			assert_eq!(parse_expiry_index_key(&expiry_index_key(key, 1)).unwrap().1, hash);
+		}
+
+		assert_ne!(expiry_index_key("a", 1), expiry_index_key("b", 1));
+		assert_eq!(parse_expiry_index_key("__exp__:soon:a"), None);
+		assert_eq!(parse_expiry_index_key("a"), None);
+	}
+
+	#[test]
+	fn chunk_fields_respects_limit() {
+		let fields = strings(&[ "a=1", "b=2", "c=3", "long=0123456789" ]);
+
+		assert_eq!(chunk_fields(fields.clone(), None), strings(&[ "a=1&b=2&c=3&long=0123456789" ]));
+		assert_eq!(chunk_fields(fields, Some(7)), strings(&[ "a=1&b=2", "c=3", "long=0123456789" ]));
+	}
+
+	fn pairs(values: &[(&str, &str)]) -> HashMap<String, String> {
+		values.iter().map(|(key, value)| (key.to_string(), value.to_string())).collect()
+	}
+
+	#[test]
+	fn checksum_pairs_is_stable_and_detects_changes() {
+		let original = pairs(&[ ("a", "1"), ("b", "2") ]);
+
+		assert_eq!(checksum_pairs(&original), checksum_pairs(&original.clone()));
+		assert_ne!(checksum_pairs(&original), checksum_pairs(&pairs(&[ ("a", "1"), ("b", "3") ])));
+		assert_ne!(checksum_pairs(&pairs(&[ ("ab", "") ])), checksum_pairs(&pairs(&[ ("a", "b") ])));
+	}
+
+	#[test]
+	fn changes_between_snapshots() {
+		let old = pairs(&[ ("kept", "1"), ("changed", "1"), ("removed", "1") ]);
+		let new = pairs(&[ ("kept", "1"), ("changed", "2"), ("added", "1") ]);
+
+		assert_eq!(Changes::between(&old, &new), Changes {
+			added: pairs(&[ ("added", "1") ]),
+			removed: pairs(&[ ("removed", "1") ]),
+			modified: pairs(&[ ("changed", "2") ]),
+		});
+		assert!(Changes::between(&old, &old).is_empty());
+	}
+
+	#[test]
+	fn error_can_be_boxed_and_chained() {
+		fn boxed(err: Error) -> Box<dyn std::error::Error + Send + Sync + 'static> {
+			Box::new(err)
+		}
+
+		let err = Error::from(String::from_utf8(vec![ 0xff ]).unwrap_err());
+		assert!(err.to_string().starts_with("failed to decode response: "));
+		assert!(std::error::Error::source(&err).is_some());
 
-fn get_url_from_env() -> String {
-	std::env::var(URL_VAR).unwrap()
+		let err = boxed(Error::Server { status: 503, body: "busy".to_string() });
+		assert_eq!(err.to_string(), "server responded with 503: busy");
+		assert!(err.source().is_none());
+		assert!(matches!(err.downcast_ref::<Error>(), Some(Error::Server { status: 503, .. })));
+	}
 }
@@ -0,0 +1,122 @@
+use crate::{ Result, SyncClient };
+
+/// A client which writes to two databases and reads from the first.
+///
+/// Every [`set`](Self::set) and [`delete`](Self::delete) is applied to the primary database
+/// and then to the mirror. Reads go to the primary, falling back to the mirror only if the
+/// request to the primary fails; a key missing from the primary is not looked up in the mirror.
+///
+/// Consistency is best-effort. The writes are separate requests, so if one fails, or another
+/// client writes in between, the two databases can disagree until the key is written again.
+///
+/// ```no_run
+/// # fn main() -> replitdb::Result<()> {
+/// let client = replitdb::MirrorClient::new(
+///     replitdb::SyncClient::new(),
+///     replitdb::SyncClient::new_url("https://mirror.example"),
+/// );
+/// client.set("greeting", "hello world")?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct MirrorClient {
+	primary: SyncClient,
+	mirror: SyncClient,
+}
+
+impl MirrorClient {
+	/// Create a client which writes to both `primary` and `mirror`, and reads from `primary`.
+	pub fn new(primary: SyncClient, mirror: SyncClient) -> Self {
+		MirrorClient { primary, mirror }
+	}
+
+	/// Get the value of the specified key from the primary database, or from the mirror if the
+	/// primary cannot be read. Returns `Ok(None)` if the key does not exist.
+	pub fn get(&self, key: impl Into<String>) -> Result<Option<String>> {
+		let key = key.into();
+
+		self.primary.get(key.as_str()).or_else(|_| self.mirror.get(key))
+	}
+
+	/// Set the value of the specified key in both databases.
+	///
+	/// The mirror is written even if writing to the primary fails, and the first error is returned.
+	pub fn set(&self, key: impl Into<String>, value: impl Into<String>) -> Result<()> {
+		let key = key.into();
+		let value = value.into();
+
+		let primary = self.primary.set(key.as_str(), value.as_str());
+		let mirror = self.mirror.set(key, value);
+
+		primary.and(mirror)
+	}
+
+	/// Delete the specified key from both databases.
+	///
+	/// The mirror is written even if writing to the primary fails, and the first error is returned.
+	pub fn delete(&self, key: impl Into<String>) -> Result<()> {
+		let key = key.into();
+
+		let primary = self.primary.delete(key.as_str());
+		let mirror = self.mirror.delete(key);
+
+		primary.and(mirror)
+	}
+
+	/// Get the client for the primary database.
+	pub fn primary(&self) -> &SyncClient {
+		&self.primary
+	}
+
+	/// Get the client for the mirror database.
+	pub fn mirror(&self) -> &SyncClient {
+		&self.mirror
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::mock::{ MockResponse, MockServer };
+
+	#[test]
+	fn writes_land_in_both_databases() {
+		let primary = MockServer::start();
+		let mirror = MockServer::start();
+		let client = MirrorClient::new(SyncClient::new_url(primary.url()), SyncClient::new_url(mirror.url()));
+
+		client.set("a", "1").unwrap();
+		client.set("b", "2").unwrap();
+		client.delete("b").unwrap();
+
+		for server in &[ &primary, &mirror ] {
+			assert_eq!(server.values().keys().collect::<Vec<&String>>(), vec![ "a" ]);
+			assert_eq!(server.values()["a"], "1");
+		}
+	}
+
+	#[test]
+	fn reads_prefer_the_primary() {
+		let primary = MockServer::start();
+		let mirror = MockServer::start();
+		primary.insert("a", "primary");
+		mirror.insert("a", "mirror");
+		let client = MirrorClient::new(SyncClient::new_url(primary.url()), SyncClient::new_url(mirror.url()));
+
+		assert_eq!(client.get("a").unwrap().as_deref(), Some("primary"));
+		assert_eq!(client.get("b").unwrap(), None);
+		assert!(mirror.requests().is_empty());
+	}
+
+	#[test]
+	fn reads_fall_back_to_the_mirror_on_failure() {
+		let primary = MockServer::with_handler(|_, _| Some(MockResponse::status(500)));
+		let mirror = MockServer::start();
+		mirror.insert("a", "mirror");
+		let client = MirrorClient::new(SyncClient::new_url(primary.url()), SyncClient::new_url(mirror.url()));
+
+		assert_eq!(client.get("a").unwrap().as_deref(), Some("mirror"));
+		assert_eq!(primary.requests().len(), 1);
+	}
+}
@@ -0,0 +1,304 @@
+//! An in-memory imitation of Replit DB served over loopback, for tests.
+
+#![allow(dead_code)]
+
+use std::collections::BTreeMap;
+use std::io::{ Read, Write };
+use std::net::{ TcpListener, TcpStream };
+use std::sync::{ Arc, Mutex };
+use std::thread;
+use std::time::Duration;
+
+use urlencoding::{ encode, decode };
+
+/// A request received by a [`MockServer`].
+#[derive(Clone, Debug)]
+pub(crate) struct MockRequest {
+	pub(crate) method: String,
+	/// The decoded key in the path, or `None` for requests to the database URL itself.
+	pub(crate) key: Option<String>,
+	/// The decoded query parameters.
+	pub(crate) query: Vec<(String, String)>,
+	pub(crate) headers: Vec<(String, String)>,
+	pub(crate) body: Vec<u8>,
+}
+
+impl MockRequest {
+	pub(crate) fn header(&self, name: &str) -> Option<&str> {
+		self.headers.iter()
+			.find(|(header, _)| header.eq_ignore_ascii_case(name))
+			.map(|(_, value)| value.as_str())
+	}
+}
+
+/// A response to send instead of the default behavior.
+#[derive(Clone, Debug)]
+pub(crate) struct MockResponse {
+	pub(crate) status: u16,
+	pub(crate) headers: Vec<(String, String)>,
+	pub(crate) body: Vec<u8>,
+	/// How long to wait between sending the head and the body.
+	pub(crate) stall: Duration,
+	/// Close the connection without responding.
+	pub(crate) reset: bool,
+}
+
+impl MockResponse {
+	pub(crate) fn status(status: u16) -> Self {
+		MockResponse {
+			status,
+			headers: Vec::new(),
+			body: Vec::new(),
+			stall: Duration::ZERO,
+			reset: false,
+		}
+	}
+
+	pub(crate) fn header(mut self, name: &str, value: &str) -> Self {
+		self.headers.push((name.to_string(), value.to_string()));
+		self
+	}
+
+	pub(crate) fn body(mut self, body: impl Into<Vec<u8>>) -> Self {
+		self.body = body.into();
+		self
+	}
+
+	pub(crate) fn reset() -> Self {
+		MockResponse {
+			reset: true,
+			..Self::status(0)
+		}
+	}
+
+	pub(crate) fn stall(mut self, stall: Duration) -> Self {
+		self.stall = stall;
+		self
+	}
+}
+
+/// The stored values and the requests received so far.
+#[derive(Debug, Default)]
+pub(crate) struct MockState {
+	pub(crate) values: BTreeMap<String, Vec<u8>>,
+	pub(crate) requests: Vec<MockRequest>,
+	/// How long to wait before answering each request.
+	pub(crate) delay: Duration,
+	in_flight: usize,
+	peak_in_flight: usize,
+}
+
+type Handler = dyn Fn(&MockRequest, &mut MockState) -> Option<MockResponse> + Send + Sync;
+
+/// A server answering each request like Replit DB, unless its handler returns a response first.
+pub(crate) struct MockServer {
+	url: String,
+	state: Arc<Mutex<MockState>>,
+}
+
+impl MockServer {
+	pub(crate) fn start() -> Self {
+		Self::with_handler(|_, _| None)
+	}
+
+	pub(crate) fn with_handler(
+		handler: impl Fn(&MockRequest, &mut MockState) -> Option<MockResponse> + Send + Sync + 'static,
+	) -> Self {
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let url = format!("http://{}", listener.local_addr().unwrap());
+
+		let state = Arc::new(Mutex::new(MockState::default()));
+		let handler: Arc<Handler> = Arc::new(handler);
+
+		let server_state = state.clone();
+		thread::spawn(move || {
+			for stream in listener.incoming() {
+				let state = server_state.clone();
+				let handler = handler.clone();
+
+				thread::spawn(move || {
+					if let Ok(stream) = stream {
+						serve(stream, &state, &*handler);
+					}
+				});
+			}
+		});
+
+		MockServer { url, state }
+	}
+
+	pub(crate) fn url(&self) -> &str {
+		&self.url
+	}
+
+	pub(crate) fn insert(&self, key: &str, value: impl Into<Vec<u8>>) {
+		self.state.lock().unwrap().values.insert(key.to_string(), value.into());
+	}
+
+	pub(crate) fn values(&self) -> BTreeMap<String, String> {
+		self.state.lock().unwrap().values.iter()
+			.map(|(key, value)| (key.clone(), String::from_utf8_lossy(value).into_owned()))
+			.collect()
+	}
+
+	pub(crate) fn requests(&self) -> Vec<MockRequest> {
+		self.state.lock().unwrap().requests.clone()
+	}
+
+	pub(crate) fn set_delay(&self, delay: Duration) {
+		self.state.lock().unwrap().delay = delay;
+	}
+
+	/// The largest number of requests that were being answered at once.
+	pub(crate) fn peak_in_flight(&self) -> usize {
+		self.state.lock().unwrap().peak_in_flight
+	}
+}
+
+fn serve(mut stream: TcpStream, state: &Mutex<MockState>, handler: &Handler) {
+	let request = match read_request(&mut stream) {
+		Some(request) => request,
+		None => return,
+	};
+
+	let delay = {
+		let mut state = state.lock().unwrap();
+		state.in_flight += 1;
+		state.peak_in_flight = state.peak_in_flight.max(state.in_flight);
+		state.delay
+	};
+	thread::sleep(delay);
+
+	let response = {
+		let mut state = state.lock().unwrap();
+		state.in_flight -= 1;
+		state.requests.push(request.clone());
+
+		match handler(&request, &mut state) {
+			Some(response) => response,
+			None => respond(&request, &mut state),
+		}
+	};
+
+	if response.reset {
+		return;
+	}
+
+	let mut head = format!("HTTP/1.1 {} Mock\r\nConnection: close\r\n", response.status);
+	for (name, value) in &response.headers {
+		head.push_str(&format!("{}: {}\r\n", name, value));
+	}
+	if !response.headers.iter().any(|(name, _)| name.eq_ignore_ascii_case("content-length")) {
+		head.push_str(&format!("Content-Length: {}\r\n", response.body.len()));
+	}
+	head.push_str("\r\n");
+
+	let _ = stream.write_all(head.as_bytes());
+	let _ = stream.flush();
+	thread::sleep(response.stall);
+	if request.method != "HEAD" {
+		let _ = stream.write_all(&response.body);
+	}
+}
+
+fn respond(request: &MockRequest, state: &mut MockState) -> MockResponse {
+	match (request.method.as_str(), &request.key) {
+		("GET", None) => {
+			let prefix = request.query.iter()
+				.find(|(name, _)| name == "prefix")
+				.map_or("", |(_, value)| value.as_str());
+
+			let keys = state.values.keys()
+				.filter(|key| key.starts_with(prefix))
+				.map(|key| encode(key).into_owned())
+				.collect::<Vec<String>>();
+
+			MockResponse::status(200).body(keys.join("\n"))
+		},
+		("GET", Some(key)) | ("HEAD", Some(key)) => match state.values.get(key) {
+			Some(value) => MockResponse::status(200).body(value.clone()),
+			None => MockResponse::status(404),
+		},
+		("POST", None) => {
+			let body = String::from_utf8_lossy(&request.body).into_owned();
+
+			for field in body.split('&').filter(|field| !field.is_empty()) {
+				let (key, value) = field.split_once('=').unwrap_or((field, ""));
+				state.values.insert(
+					decode(key).unwrap().into_owned(),
+					decode(value).unwrap().into_owned().into_bytes(),
+				);
+			}
+
+			MockResponse::status(200)
+		},
+		("DELETE", Some(key)) => {
+			state.values.remove(key);
+			MockResponse::status(204)
+		},
+		_ => MockResponse::status(405),
+	}
+}
+
+fn read_request(stream: &mut TcpStream) -> Option<MockRequest> {
+	let mut data = Vec::new();
+	let mut buffer = [0; 4096];
+
+	let head_end = loop {
+		if let Some(end) = data.windows(4).position(|window| window == b"\r\n\r\n") {
+			break end;
+		}
+
+		let read = stream.read(&mut buffer).ok()?;
+		if read == 0 {
+			return None;
+		}
+		data.extend_from_slice(&buffer[..read]);
+	};
+
+	let head = String::from_utf8_lossy(&data[..head_end]).into_owned();
+	let mut lines = head.split("\r\n");
+
+	let mut request_line = lines.next()?.split(' ');
+	let method = request_line.next()?.to_string();
+	let target = request_line.next()?.to_string();
+
+	let headers = lines
+		.filter_map(|line| line.split_once(':'))
+		.map(|(name, value)| (name.trim().to_string(), value.trim().to_string()))
+		.collect::<Vec<(String, String)>>();
+
+	let length = headers.iter()
+		.find(|(name, _)| name.eq_ignore_ascii_case("content-length"))
+		.and_then(|(_, value)| value.parse::<usize>().ok())
+		.unwrap_or(0);
+
+	let mut body = data[head_end + 4..].to_vec();
+	while body.len() < length {
+		let read = stream.read(&mut buffer).ok()?;
+		if read == 0 {
+			break;
+		}
+		body.extend_from_slice(&buffer[..read]);
+	}
+
+	let (path, query) = target.split_once('?').unwrap_or((&target, ""));
+	let path = path.trim_start_matches('/');
+
+	let key = if path.is_empty() {
+		None
+	} else {
+		Some(decode(path).ok()?.into_owned())
+	};
+
+	let query = query.split('&')
+		.filter(|pair| !pair.is_empty())
+		.map(|pair| {
+			let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+			let value = value.replace('+', " ");
+			(decode(name).unwrap().into_owned(), decode(&value).unwrap().into_owned())
+		})
+		.collect();
+
+	Some(MockRequest { method, key, query, headers, body })
+}
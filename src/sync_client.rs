@@ -1,119 +1,787 @@
-use crate::get_url_from_env;
+use crate::{
+	checksum_pairs,
+	chunk_fields,
+	collapse_to_depth,
+	compare_values,
+	content_length,
+	dedup_pairs,
+	expiry_index_key,
+	file_name_to_key,
+	get_url_from_env,
+	is_transient,
+	key_to_file_name,
+	next_idempotency_key,
+	next_request_id,
+	parse_expiry_index_key,
+	retry_after,
+	unix_seconds,
+	CheckResult,
+	Error,
+	Hook,
+	Options,
+	RequestInfo,
+	ResponseInfo,
+	Result,
+	CHECK_KEY,
+	EMPTY_CONFIRMATION,
+	EXPIRY_PREFIX,
+	IDEMPOTENCY_KEY_HEADER,
+	REQUEST_ID_HEADER,
+};
 
-use std::collections::HashMap;
+use std::collections::{ HashMap, HashSet };
+use std::fmt::Display;
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+use std::sync::Arc;
+use std::thread;
+use std::time::{ Duration, Instant, SystemTime };
 
-use reqwest::blocking::Client as HttpClient;
+use reqwest::Method;
+use reqwest::blocking::{ Client as HttpClient, Request, RequestBuilder, Response };
+use reqwest::header::{ IF_UNMODIFIED_SINCE, LAST_MODIFIED };
 
+#[cfg(feature = "cache")]
+use crate::cache::ListCache;
+
+#[cfg(feature = "serde")]
+use crate::DbValue;
+
+#[cfg(feature = "json")]
+use serde::{ de::DeserializeOwned, Serialize };
+
+use url::Url;
 use urlencoding::{ encode, decode };
 
 /// A blocking client.
-/// 
+///
 /// Provides synchronous functions for interacting with the database.
 /// For an asynchronous, non-blocking client, use [`AsyncClient`](super::AsyncClient).
-/// 
-/// ```
+///
+/// ```no_run
+/// # fn main() -> Result<(), replitdb::Error> {
 /// let client = replitdb::SyncClient::new();
 /// client.set("greeting", "hello world")?;
-/// println!("{}", client.get("greeting")?);
+/// println!("{:?}", client.get("greeting")?);
+/// # Ok(())
+/// # }
 /// ```
 #[derive(Clone, Debug)]
 pub struct Client {
 	url: String,
 	client: HttpClient,
+	options: Options,
 }
 
 impl Client {
 	/// Create a new synchronous client, fetching the URL from an environment variable.
-	/// 
+	///
 	/// # Panics
-	/// 
-	/// Panics if the database URL environment variable (`REPLIT_DB_URL`) is not set.
+	///
+	/// Panics if the database URL environment variable (`REPLIT_DB_URL`) is not set. Use
+	/// [`try_new`](Self::try_new) to handle this case instead.
 	pub fn new() -> Self {
-		Client {
-			url: get_url_from_env(),
+		Self::try_new().expect("REPLIT_DB_URL must be set to the database URL")
+	}
+
+	/// Create a new synchronous client, fetching the URL from an environment variable.
+	///
+	/// Returns [`Error::MissingEnvVar`] if the database URL environment variable (`REPLIT_DB_URL`)
+	/// is not set.
+	pub fn try_new() -> Result<Self> {
+		Ok(Client {
+			url: get_url_from_env()?,
 			client: HttpClient::new(),
-		}
+			options: Options::default(),
+		})
 	}
 
 	/// Create a new synchronous client, specifying a custom database URL.
 	pub fn new_url(url: impl Into<String>) -> Self {
+		Self::new_with_client(url, HttpClient::new())
+	}
+
+	/// Create a new synchronous client for a custom database URL, sending requests with the provided
+	/// HTTP client instead of creating a new connection pool.
+	///
+	/// Setting any of the timeouts replaces `client` with one built by this crate.
+	pub fn new_with_client(url: impl Into<String>, client: HttpClient) -> Self {
 		Client {
 			url: url.into(),
-			client: HttpClient::new(),
+			client,
+			options: Options::default(),
+		}
+	}
+
+	/// Create a new synchronous client, specifying a custom database URL.
+	/// Unlike [`new_url`](Self::new_url), returns [`Error::InvalidUrl`] if the URL is malformed.
+	pub fn try_new_url(url: impl Into<String>) -> Result<Self> {
+		let url = url.into();
+		Url::parse(&url)?;
+
+		Ok(Self::new_url(url))
+	}
+
+	/// Clone the client, pointing the clone at a different database URL.
+	/// The clone shares the HTTP connection pool and options of the original, but not its caches.
+	pub fn clone_with_url(&self, url: impl Into<String>) -> Self {
+		Client {
+			url: url.into(),
+			client: self.client.clone(),
+			options: self.options.without_caches(),
 		}
 	}
 
-	/// Get the value of the specified key. Returns `Ok(None)` if the key does not exist.
+	/// Cache the results of [`list_prefix`](Self::list_prefix) for `ttl`, keyed by prefix.
+	///
+	/// A successful `set` or `delete` of a key evicts the cached listings of every prefix the key
+	/// starts with. Writes made by other clients are not detected until the entries expire.
+	#[cfg(feature = "cache")]
+	pub fn cache_lists(mut self, ttl: Duration) -> Self {
+		self.options.list_cache = Some(Arc::new(ListCache::new(ttl)));
+		self
+	}
+
+	/// Set the timeout applied to each request, or `None` for the HTTP client's default of 30
+	/// seconds.
+	///
+	/// This rebuilds the underlying HTTP client, so clones made before the call keep the old timeout.
+	pub fn set_timeout(&mut self, timeout: Option<Duration>) -> Result<()> {
+		self.options.timeout = timeout;
+		self.client = build_http_client(&self.options)?;
+
+		Ok(())
+	}
+
+	/// Set the timeout for establishing a connection, or `None` for no separate connect timeout.
+	/// This lets requests to an unreachable host fail quickly without limiting how long a slow
+	/// response may take once connected.
+	///
+	/// This rebuilds the underlying HTTP client, so clones made before the call keep the old timeout.
+	pub fn set_connect_timeout(&mut self, timeout: Option<Duration>) -> Result<()> {
+		self.options.connect_timeout = timeout;
+		self.client = build_http_client(&self.options)?;
+
+		Ok(())
+	}
+
+	/// Set the timeout for each read, or `None` for no separate read timeout. The timeout applies
+	/// to waiting for a response and to each read of its body, restarting after every read, so
+	/// large values that arrive steadily are not cut off.
+	///
+	/// The blocking HTTP client applies [`set_timeout`](Self::set_timeout) in the same way, so if
+	/// both are set, the shorter of the two is used. This rebuilds the underlying HTTP client, so
+	/// clones made before the call keep the old timeout.
+	pub fn set_read_timeout(&mut self, timeout: Option<Duration>) -> Result<()> {
+		self.options.read_timeout = timeout;
+		self.client = build_http_client(&self.options)?;
+
+		Ok(())
+	}
+
+	/// Prefix every key with `prefix` before any [`key_transform`](Self::key_transform), or remove
+	/// the prefix with `None`. Listed keys are returned without the prefix, so a client can be
+	/// moved between namespaces without reconstruction.
+	///
+	/// This clears the client's cached listings, which were made under the old prefix.
+	pub fn set_prefix(&mut self, prefix: Option<String>) -> Result<()> {
+		self.options = Options {
+			key_prefix: prefix,
+			..self.options.without_caches()
+		};
+
+		Ok(())
+	}
+
+	/// Set the timeout for establishing a connection, as
+	/// [`set_connect_timeout`](Self::set_connect_timeout) does.
+	///
+	/// # Panics
+	///
+	/// Panics if the HTTP client cannot be rebuilt, as [`HttpClient::new`] does.
+	pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+		self.set_connect_timeout(Some(timeout)).expect("failed to build HTTP client");
+		self
+	}
+
+	/// Set the timeout for each read, as [`set_read_timeout`](Self::set_read_timeout) does.
+	///
+	/// # Panics
+	///
+	/// Panics if the HTTP client cannot be rebuilt, as [`HttpClient::new`] does.
+	pub fn read_timeout(mut self, timeout: Duration) -> Self {
+		self.set_read_timeout(Some(timeout)).expect("failed to build HTTP client");
+		self
+	}
+
+	/// Call `hook` after every request with information about the request and its response.
+	///
+	/// The hook is also called when a request fails without a response, in which case the status
+	/// and content length are `None`.
+	pub fn inspect(mut self, hook: impl Fn(&RequestInfo, &ResponseInfo) + Send + Sync + 'static) -> Self {
+		self.options.inspect = Some(Hook(Arc::new(hook)));
+		self
+	}
+
+	/// Retry failed requests up to `count` times, waiting [`retry_backoff`](Self::retry_backoff)
+	/// before the first retry and doubling the wait before each one after. By default requests are
+	/// not retried.
+	///
+	/// Reads and deletes are retried on connection errors, timeouts and `5xx` responses. Writes
+	/// are only retried if the connection could not be established, since a write whose response
+	/// was lost may already have been applied.
+	///
+	/// A connection the server or a proxy resets or closes before responding, for example with
+	/// an HTTP/2 `GOAWAY`, is reported by reqwest as a request error
+	/// ([`is_request`](reqwest::Error::is_request)) and counts as a connection error. The broken
+	/// connection is dropped from the pool, so the retry is made on a new one.
+	///
+	/// Any request answered with `429 Too Many Requests` is retried, as it was not applied, after
+	/// the delay given by the response's `Retry-After` header if there is one.
+	pub fn retries(mut self, count: u32) -> Self {
+		self.options.retries = count;
+		self
+	}
+
+	/// Set the wait before the first retry enabled by [`retries`](Self::retries). Defaults to 100 ms.
+	pub fn retry_backoff(mut self, base: Duration) -> Self {
+		self.options.retry_backoff = Some(base);
+		self
+	}
+
+	/// Make [`empty`](Self::empty), [`empty_confirmed`](Self::empty_confirmed) and
+	/// [`delete_prefix`](Self::delete_prefix) attempt to delete every key even if some deletes fail,
+	/// returning [`Error::DeleteFailed`] with the keys that could not be deleted at the end. By
+	/// default the first failure aborts the remaining deletes.
+	pub fn best_effort_deletes(mut self, enabled: bool) -> Self {
+		self.options.best_effort_deletes = enabled;
+		self
+	}
+
+	/// Send a unique `Idempotency-Key` header with every write, so that servers supporting it can
+	/// ignore duplicate deliveries of the same write.
+	///
+	/// Replit DB itself ignores the header; this is intended for custom backends in front of it.
+	/// A retried write is sent with the same key as the original.
+	pub fn idempotency_keys(mut self, enabled: bool) -> Self {
+		self.options.idempotency_keys = enabled;
+		self
+	}
+
+	/// Send an `Authorization: Bearer` header with the provided token on every request.
+	///
+	/// Replit DB authenticates through the database URL; this is intended for gateways in front of it.
+	pub fn bearer_token(mut self, token: impl Into<String>) -> Self {
+		self.options.bearer_token = Some(token.into());
+		self
+	}
+
+	/// Store keys longer than Replit DB's 1000 byte limit under their SHA-256 hash, so that
+	/// [`get`](Self::get), [`set`](Self::set) and [`delete`](Self::delete) accept keys of any length.
+	///
+	/// A long key is stored as `__hash__:` followed by the hex-encoded hash of the key, before any
+	/// [`key_transform`](Self::key_transform) or prefix is applied, so it stays in the same
+	/// namespace as the client's other keys. Only the hash is stored, so listing returns the hashed
+	/// form and the original key cannot be recovered from it. Distinct keys are assumed never to
+	/// share a hash.
+	pub fn hash_long_keys(mut self, enabled: bool) -> Self {
+		self.options.hash_long_keys = enabled;
+		self
+	}
+
+	/// Rewrite every key with `transform` before it is sent to the server, for example to add an
+	/// environment prefix, and map keys returned by listing back through `inverse`. This also
+	/// applies to the prefixes passed to [`list_prefix`](Self::list_prefix).
+	///
+	/// `inverse(transform(key))` must equal `key` for every key. For listing by prefix to work, the
+	/// transform must preserve prefixes, as adding a fixed prefix does.
+	pub fn key_transform(
+		mut self,
+		transform: impl Fn(&str) -> String + Send + Sync + 'static,
+		inverse: impl Fn(&str) -> String + Send + Sync + 'static,
+	) -> Self {
+		self.options.key_transform = Some((Hook(Arc::new(transform)), Hook(Arc::new(inverse))));
+		self
+	}
+
+	/// Limit the size of a single value read by [`get`](Self::get) and [`get_bytes`](Self::get_bytes)
+	/// to `limit` bytes, returning [`Error::ValueTooLarge`] for larger values instead of reading them
+	/// into memory. By default there is no limit.
+	pub fn max_value_read_bytes(mut self, limit: u64) -> Self {
+		self.options.max_value_read_bytes = Some(limit);
+		self
+	}
+
+	/// Split the requests made by [`set_many`](Self::set_many) so that each body is at most
+	/// `limit` bytes once encoded. A pair which is larger than `limit` on its own is written in a
+	/// request by itself. By default all pairs are sent in one request.
+	pub fn set_many_chunk_bytes(mut self, limit: usize) -> Self {
+		self.options.set_many_chunk_bytes = Some(limit);
+		self
+	}
+
+	/// Send the provided `Accept` header with requests made by [`get`](Self::get).
+	pub fn accept(mut self, value: impl Into<String>) -> Self {
+		self.options.accept = Some(value.into());
+		self
+	}
+
+	/// Send a unique request ID in the `X-Request-Id` header of every request, so that server logs
+	/// can be correlated with client logs. When the `tracing` feature is enabled, the same ID is
+	/// recorded as the `id` field of each request's span.
+	pub fn request_id_header(mut self, enabled: bool) -> Self {
+		self.options.request_id_header = enabled;
+		self
+	}
+
+	/// Allow writes to keys starting with a reserved prefix such as `__meta__:`.
+	/// By default, such writes return [`Error::ReservedKey`].
+	pub fn allow_reserved_keys(mut self, allow: bool) -> Self {
+		self.options.allow_reserved_keys = allow;
+		self
+	}
+
+	/// Limit the total size of the values fetched by [`get_all`](Self::get_all).
+	///
+	/// Before fetching any values, the size of each one is requested with a `HEAD` request, and
+	/// [`Error::TooLarge`] is returned if their sum exceeds `limit`. Values whose size the server
+	/// does not report are counted as empty.
+	pub fn max_total_bytes(mut self, limit: u64) -> Self {
+		self.options.max_total_bytes = Some(limit);
+		self
+	}
+
+	/// Trim leading and trailing whitespace from values returned by [`get`](Self::get),
+	/// [`get_lossy`](Self::get_lossy) and [`get_all`](Self::get_all). Off by default.
+	pub fn trim_values(mut self, trim: bool) -> Self {
+		self.options.trim_values = trim;
+		self
+	}
+
+	/// Get the value of the specified key. Returns `Ok(None)` if the key does not exist,
+	/// and `Ok(Some(String::new()))` if it exists with an empty value.
+	///
+	/// Returns [`Error::Decode`] if the value is not valid UTF-8; use [`get_lossy`](Self::get_lossy)
+	/// to replace invalid sequences instead.
 	pub fn get(
 		&self,
 		key: impl Into<String>,
-	) -> Result<Option<String>, String> {
-		let encoded_key = encode(key.into().as_str()).into_owned();
+	) -> Result<Option<String>> {
+		match self.get_bytes(key)? {
+			Some(bytes) => Ok(Some(self.options.trim(String::from_utf8(bytes)?))),
+			None => Ok(None),
+		}
+	}
+
+	/// Get the value of the specified key, parsed as JSON if possible and as plain text otherwise.
+	/// Returns `Ok(None)` if the key does not exist.
+	///
+	/// Any valid JSON is parsed, so text such as `123` or `true` is returned as [`DbValue::Json`].
+	#[cfg(feature = "serde")]
+	pub fn get_dynamic(
+		&self,
+		key: impl Into<String>,
+	) -> Result<Option<DbValue>> {
+		Ok(
+			self.get(key)?
+				.map(|value| match serde_json::from_str(&value) {
+					Ok(json) => DbValue::Json(json),
+					Err(_) => DbValue::Text(value),
+				})
+		)
+	}
 
-		let response = self.client.get(format!("{}/{}", self.url, encoded_key))
-			.send()
-			.map_err(|err| err.to_string())?;
+	/// Get the value of the specified key, deserialized from JSON. Returns `Ok(None)` if the key
+	/// does not exist, and [`Error::Json`] if the value is not valid JSON for `T`.
+	#[cfg(feature = "json")]
+	pub fn get_json<T: DeserializeOwned>(
+		&self,
+		key: impl Into<String>,
+	) -> Result<Option<T>> {
+		match self.get(key)? {
+			Some(value) => Ok(Some(serde_json::from_str(&value)?)),
+			None => Ok(None),
+		}
+	}
 
-		if response.status().is_success() {
-			Ok(Some(
-				response.text()
-					.map_err(|err| err.to_string())?
-			))
-		} else if response.status().as_u16() == 404 {
-			Ok(None)
-		} else {
-			Err(
-				response.text()
-					.map_err(|err| err.to_string())?
-			)
+	/// Get the value of the specified key, replacing invalid UTF-8 sequences with `U+FFFD`.
+	/// Returns `Ok(None)` if the key does not exist.
+	pub fn get_lossy(
+		&self,
+		key: impl Into<String>,
+	) -> Result<Option<String>> {
+		Ok(
+			self.get_bytes(key)?
+				.map(|bytes| self.options.trim(String::from_utf8_lossy(&bytes).into_owned()))
+		)
+	}
+
+	/// Get the raw bytes of the value of the specified key. Returns `Ok(None)` if the key does not exist.
+	pub fn get_bytes(
+		&self,
+		key: impl Into<String>,
+	) -> Result<Option<Vec<u8>>> {
+		match self.get_response(key.into())? {
+			Some(response) => Ok(Some(self.read_body(response)?)),
+			None => Ok(None),
 		}
 	}
 
+	/// Get the value of the specified key along with its modification time, as reported by the
+	/// server's `Last-Modified` header. Returns `Ok(None)` if the key does not exist.
+	///
+	/// The time is `None` if the server does not send the header or it cannot be parsed.
+	pub fn get_with_modified(
+		&self,
+		key: impl Into<String>,
+	) -> Result<Option<(String, Option<SystemTime>)>> {
+		let response = match self.get_response(key.into())? {
+			Some(response) => response,
+			None => return Ok(None),
+		};
+
+		let modified = response.headers().get(LAST_MODIFIED)
+			.and_then(|value| value.to_str().ok())
+			.and_then(|value| httpdate::parse_http_date(value).ok());
+
+		let value = String::from_utf8(self.read_body(response)?)?;
+
+		Ok(Some((self.options.trim(value), modified)))
+	}
+
+	/// Get the first of `keys` that exists, returning it along with its value.
+	/// Returns `Ok(None)` if none of the keys exist.
+	///
+	/// Keys are tried in order, and no further requests are made once one is found.
+	pub fn get_first(&self, keys: &[&str]) -> Result<Option<(String, String)>> {
+		for &key in keys {
+			if let Some(value) = self.get(key)? {
+				return Ok(Some((key.to_string(), value)));
+			}
+		}
+
+		Ok(None)
+	}
+
+	/// Check whether the specified key exists, without reading its value.
+	///
+	/// A `HEAD` request is made, falling back to a `GET` whose body is discarded if the server
+	/// does not allow `HEAD`.
+	pub fn exists(&self, key: impl Into<String>) -> Result<bool> {
+		let key = key.into();
+		let encoded_key = encode(&self.options.storage_key(&key)?).into_owned();
+
+		let request = self.client.head(format!("{}/{}", self.url, encoded_key));
+
+		let response = self.send(request, Some(&key))?;
+
+		match response.status().as_u16() {
+			404 => Ok(false),
+			405 => Ok(self.get_response(key)?.is_some()),
+			_ if response.status().is_success() => Ok(true),
+			_ => Err(server_error(response)),
+		}
+	}
+
+	/// Check which of the specified keys exist, without reading their values.
+	pub fn exists_many<K: Into<String>>(
+		&self,
+		keys: impl IntoIterator<Item = K>,
+	) -> Result<HashMap<String, bool>> {
+		let mut out = HashMap::new();
+
+		for key in keys {
+			let key = key.into();
+			let exists = self.exists(key.as_str())?;
+			out.insert(key, exists);
+		}
+
+		Ok(out)
+	}
+
 	/// Set the value of the specified key to the provided value.
+	///
+	/// Returns [`Error::ReservedKey`] if the key starts with a reserved prefix, unless
+	/// [`allow_reserved_keys`](Self::allow_reserved_keys) is set.
+	/// Every method taking a key returns [`Error::InvalidKey`] without making a request if the
+	/// key is empty, `.` or `..`, or contains a newline.
 	pub fn set(
 		&self,
 		key: impl Into<String>,
 		value: impl Into<String>,
-	) -> Result<(), String> {
-		let encoded_key = encode(key.into().as_str()).into_owned();
+	) -> Result<()> {
+		let key = key.into();
+		self.options.check_reserved(&key)?;
+
+		self.set_unchecked(key, value)
+	}
+
+	/// Set the value of the specified key, but only if it has not been modified since `since`,
+	/// returning [`Error::PreconditionFailed`] otherwise.
+	///
+	/// The condition is sent in an `If-Unmodified-Since` header, so it is only enforced by servers
+	/// that support it; Replit DB itself ignores the header and always writes the value. The time
+	/// is sent with one-second precision.
+	pub fn set_if_unmodified_since(
+		&self,
+		key: impl Into<String>,
+		value: impl Into<String>,
+		since: SystemTime,
+	) -> Result<()> {
+		let key = key.into();
+		self.options.check_reserved(&key)?;
+
+		let encoded_key = encode(&self.options.storage_key(&key)?).into_owned();
 		let encoded_value = encode(value.into().as_str()).into_owned();
 
-		let response = self.client.post(self.url.clone())
-			.body(format!("{}={}", encoded_key, encoded_value))
-			.header("Content-Type", "application/x-www-form-urlencoded")
-			.send()
-			.map_err(|err| err.to_string())?;
-		
+		let request = self.post(format!("{}={}", encoded_key, encoded_value))
+			.header(IF_UNMODIFIED_SINCE, httpdate::fmt_http_date(since));
+
+		let response = self.send(request, Some(&key))?;
+
+		if response.status().is_success() {
+			self.options.invalidate(&key);
+			Ok(())
+		} else if response.status().as_u16() == 412 {
+			Err(Error::PreconditionFailed)
+		} else {
+			Err(server_error(response))
+		}
+	}
+
+	/// Set the value of the specified key, to be deleted by [`sweep_expired`](Self::sweep_expired)
+	/// once `ttl` has passed.
+	///
+	/// Alongside the value, the key is written to an expiry index, under the key
+	/// `__exp__:<expiry>:<hash>` where `<expiry>` is the zero-padded Unix time in seconds and
+	/// `<hash>` is the hex SHA-256 hash of the key. Both are written in a single request. The key
+	/// stays readable until it is swept. Setting the key again with a longer TTL extends it, as the
+	/// sweep follows the entry that expires last. The index entry is not removed if the key is
+	/// later overwritten with [`set`](Self::set), so it will still be deleted.
+	pub fn set_with_ttl(
+		&self,
+		key: impl Into<String>,
+		value: impl Into<String>,
+		ttl: Duration,
+	) -> Result<()> {
+		let key = key.into();
+		self.options.check_reserved(&key)?;
+
+		let expires = unix_seconds(SystemTime::now() + ttl);
+		let index_key = expiry_index_key(&key, expires);
+
+		let request = self.post(format!(
+			"{}={}&{}={}",
+			encode(&self.options.storage_key(&key)?),
+			encode(&value.into()),
+			encode(&self.options.storage_key(&index_key)?),
+			encode(&key),
+		));
+
+		let response = self.send(request, Some(&key))?;
+
 		if response.status().is_success() {
+			self.options.invalidate(&key);
+			self.options.invalidate(&index_key);
 			Ok(())
 		} else {
-			Err(
-				response.text()
-					.map_err(|err| err.to_string())?
-			)
+			Err(server_error(response))
+		}
+	}
+
+	/// Delete every key written by [`set_with_ttl`](Self::set_with_ttl) whose TTL has passed,
+	/// returning the number of keys deleted.
+	///
+	/// The expiry index is listed, and only the index entries that are due are read to find their
+	/// keys, so values are never read. When a key has several entries, only the latest expiry
+	/// counts and the earlier entries are dropped once due. Each due key is deleted before its
+	/// latest index entry, so a failure part way through leaves the remaining keys to be swept by
+	/// the next call.
+	pub fn sweep_expired(&self) -> Result<usize> {
+		let now = unix_seconds(SystemTime::now());
+
+		let mut entries: HashMap<String, Vec<(u64, String)>> = HashMap::new();
+		for index_key in self.list_prefix(EXPIRY_PREFIX)? {
+			if let Some((expires, hash)) = parse_expiry_index_key(&index_key) {
+				let hash = hash.to_string();
+				entries.entry(hash).or_default().push((expires, index_key));
+			}
+		}
+
+		let mut count = 0;
+		for mut entries in entries.into_values() {
+			entries.sort();
+			let (latest, latest_key) = match entries.pop() {
+				Some(latest) => latest,
+				None => continue,
+			};
+
+			for (expires, index_key) in entries {
+				if expires <= now {
+					self.delete(index_key)?;
+				}
+			}
+
+			if latest <= now {
+				if let Some(key) = self.get_bytes(latest_key.as_str())? {
+					self.delete(String::from_utf8(key)?)?;
+					count += 1;
+				}
+				self.delete(latest_key)?;
+			}
+		}
+
+		Ok(count)
+	}
+
+	/// Set the value of the specified key if `value` is `Some`, or delete the key if it is `None`.
+	pub fn put(
+		&self,
+		key: impl Into<String>,
+		value: Option<impl Into<String>>,
+	) -> Result<()> {
+		match value {
+			Some(value) => self.set(key, value),
+			None => self.delete(key),
+		}
+	}
+
+	/// Set the value of the specified key to the formatted form of `value`.
+	pub fn set_display<V: Display>(
+		&self,
+		key: impl Into<String>,
+		value: V,
+	) -> Result<()> {
+		self.set(key, value.to_string())
+	}
+
+	/// Set the value of the specified key to `value` serialized as JSON.
+	#[cfg(feature = "json")]
+	pub fn set_json<T: Serialize + ?Sized>(
+		&self,
+		key: impl Into<String>,
+		value: &T,
+	) -> Result<()> {
+		self.set(key, serde_json::to_string(value)?)
+	}
+
+	/// Set multiple keys to their provided values in a single request.
+	///
+	/// If a key appears more than once, only its last value is written. If
+	/// [`set_many_chunk_bytes`](Self::set_many_chunk_bytes) is set, the pairs are split across as
+	/// many requests as needed to keep each body under the limit, and if a request fails, the
+	/// pairs in earlier requests have already been written.
+	pub fn set_many<K: Into<String>, V: Into<String>>(
+		&self,
+		pairs: impl IntoIterator<Item = (K, V)>,
+	) -> Result<()> {
+		let pairs = dedup_pairs(pairs);
+		if pairs.is_empty() {
+			return Ok(());
+		}
+
+		for (key, _) in &pairs {
+			self.options.check_reserved(key)?;
+		}
+
+		let fields = pairs.iter()
+			.map(|(key, value)| Ok(format!("{}={}", encode(&self.options.storage_key(key)?), encode(value))))
+			.collect::<Result<Vec<String>>>()?;
+
+		let result = self.set_chunks(chunk_fields(fields, self.options.set_many_chunk_bytes));
+
+		for (key, _) in &pairs {
+			self.options.invalidate(key);
 		}
+
+		result
+	}
+
+	/// Add `delta` to the floating-point value of the specified key, returning the new value.
+	/// A missing key is treated as `0.0`.
+	///
+	/// Returns [`Error::Parse`] if the current value is not a number. The read and write are
+	/// separate requests, so concurrent increments of the same key may be lost.
+	pub fn increment_float(
+		&self,
+		key: impl Into<String>,
+		delta: f64,
+	) -> Result<f64> {
+		let key = key.into();
+
+		let current = match self.get(key.clone())? {
+			Some(value) => value.trim().parse::<f64>().map_err(|_| Error::Parse(value))?,
+			None => 0.0,
+		};
+
+		let value = current + delta;
+		self.set(key, value.to_string())?;
+
+		Ok(value)
 	}
 
 	/// Delete the specified key from the database.
-	pub fn delete(&self, key: impl Into<String>) -> Result<(), String> {
-		let encoded_key = encode(key.into().as_str()).into_owned();
+	pub fn delete(&self, key: impl Into<String>) -> Result<()> {
+		let key = key.into();
+		let encoded_key = encode(&self.options.storage_key(&key)?).into_owned();
+
+		let request = self.client.delete(format!("{}/{}", self.url, encoded_key));
+
+		let response = self.send(request, Some(&key))?;
 
-		let response = self.client.delete(format!("{}/{}", self.url, encoded_key))
-			.send()
-			.map_err(|err| err.to_string())?;
-		
 		if response.status().is_success() || response.status().as_u16() == 404 {
+			self.options.invalidate(&key);
 			Ok(())
 		} else {
-			Err(
-				response.text()
-					.map_err(|err| err.to_string())?
-			)
+			Err(server_error(response))
+		}
+	}
+
+	/// Move the value of the key `from` to the key `to`, returning [`Error::MissingKey`] if `from`
+	/// does not exist.
+	///
+	/// The value is read, written to `to`, and only then deleted from `from`, so a failure never
+	/// loses the value, though it may leave it under both keys. Any existing value of `to` is
+	/// overwritten, and renaming a key to itself leaves it unchanged.
+	pub fn rename(
+		&self,
+		from: impl Into<String>,
+		to: impl Into<String>,
+	) -> Result<()> {
+		let from = from.into();
+
+		let value = match self.get_bytes(from.as_str())? {
+			Some(value) => String::from_utf8(value)?,
+			None => return Err(Error::MissingKey(from)),
+		};
+
+		let to = to.into();
+		if self.options.storage_key(&from)? == self.options.storage_key(&to)? {
+			return Ok(());
 		}
+
+		self.set(to, value)?;
+		self.delete(from)
+	}
+
+	/// Delete each of the specified keys from the database.
+	///
+	/// Replit DB has no endpoint for deleting several keys in one request, so one request is made
+	/// per key. The first failure aborts the remaining deletes.
+	pub fn delete_many<K: Into<String>>(
+		&self,
+		keys: impl IntoIterator<Item = K>,
+	) -> Result<()> {
+		for key in keys {
+			self.delete(key)?;
+		}
+
+		Ok(())
 	}
 
 	/// List all keys in the database.
-	pub fn list(&self) -> Result<Vec<String>, String> {
+	pub fn list(&self) -> Result<Vec<String>> {
 		self.list_prefix("")
 	}
 
@@ -121,63 +789,1639 @@ impl Client {
 	pub fn list_prefix(
 		&self,
 		prefix: impl Into<String>,
-	) -> Result<Vec<String>, String> {
-		let response = self.client.get(self.url.clone())
-			.query(&[ ("encode", "true"), ("prefix", prefix.into().as_str()) ])
-			.send()
-			.map_err(|err| err.to_string())?;
-		
+	) -> Result<Vec<String>> {
+		let prefix = prefix.into();
+
+		#[cfg(feature = "cache")]
+		if let Some(keys) = self.options.list_cache.as_ref().and_then(|cache| cache.get(&prefix)) {
+			return Ok(keys);
+		}
+
+		let request = self.client.get(self.url.clone())
+			.query(&[ ("encode", "true"), ("prefix", self.options.transform_key(&prefix).as_str()) ]);
+
+		let response = self.send(request, None)?;
+
 		if response.status().is_success() {
-			let text = response.text()
-				.map_err(|err| err.to_string())?;
-
-			Ok(
-				text.split("\n")
-					.map(|key| Ok(
-						decode(key.into())
-							.map_err(|err| err.to_string())?
-							.into_owned()
-					))
-					.collect::<Result<Vec<String>, String>>()?
-			)
+			let text = response.text()?;
+
+			let keys = text.split('\n')
+				.filter(|key| !key.is_empty())
+				.map(|key| Ok(self.options.untransform_key(decode(key)?.into_owned())))
+				.collect::<Result<Vec<String>>>()?;
+
+			#[cfg(feature = "cache")]
+			if let Some(cache) = &self.options.list_cache {
+				cache.insert(prefix, keys.clone());
+			}
+
+			Ok(keys)
 		} else {
-			Err(
-				response.text()
-					.map_err(|err| err.to_string())?
-			)
+			Err(server_error(response))
 		}
 	}
 
-	/// Delete all keys in the database.
-	pub fn empty(&self) -> Result<(), String> {
-		// this could probably be improved
+	/// List at most `limit` keys that start with the specified prefix.
+	///
+	/// Replit DB has no way to limit a listing, so every matching key is still downloaded and the
+	/// result is truncated afterwards. Since the server does not order keys, which keys are
+	/// returned is unspecified.
+	pub fn list_prefix_limited(
+		&self,
+		prefix: impl Into<String>,
+		limit: usize,
+	) -> Result<Vec<String>> {
+		let mut keys = self.list_prefix(prefix)?;
+		keys.truncate(limit);
 
-		let keys = self.list()?;
+		Ok(keys)
+	}
+
+	/// List all keys in the database that start with `prefix` and end with `suffix`.
+	///
+	/// Only the prefix is filtered by the server; the suffix is matched after the keys are downloaded.
+	pub fn list_matching(
+		&self,
+		prefix: &str,
+		suffix: &str,
+	) -> Result<Vec<String>> {
+		let mut keys = self.list_prefix(prefix)?;
+		keys.retain(|key| key.ends_with(suffix));
+
+		Ok(keys)
+	}
+
+	/// List the distinct paths under `prefix`, treating `separator` as a path separator and
+	/// collapsing keys nested deeper than `max_depth` segments into their ancestor.
+	///
+	/// For example, with the keys `a:b:c`, `a:b:d` and `a:e`, listing the prefix `a:` with a
+	/// separator of `:` and a depth of 1 returns `a:b` and `a:e`. Paths are returned sorted.
+	pub fn list_depth(
+		&self,
+		prefix: &str,
+		separator: char,
+		max_depth: usize,
+	) -> Result<Vec<String>> {
+		let keys = self.list_prefix(prefix)?;
+
+		Ok(collapse_to_depth(prefix, keys, separator, max_depth))
+	}
+
+	/// List the key-value pairs under `prefix`, sorted by value.
+	///
+	/// Values that parse as numbers are compared numerically and sorted before all other values,
+	/// which are compared lexicographically, as is `NaN`. Every value under the prefix is fetched,
+	/// one request per key, so this is only suitable for small sets of keys.
+	pub fn list_sorted_by_value(
+		&self,
+		prefix: &str,
+		descending: bool,
+	) -> Result<Vec<(String, String)>> {
+		let mut pairs = Vec::new();
+
+		let keys = self.list_prefix(prefix)?;
 		for key in keys {
-			self.delete(key)?;
+			if let Some(value) = self.get(key.clone())? {
+				pairs.push((key, value));
+			}
 		}
 
-		Ok(())
+		pairs.sort_by(|(_, a), (_, b)| compare_values(a, b));
+		if descending {
+			pairs.reverse();
+		}
+
+		Ok(pairs)
 	}
 
-	/// Get all key-value pairs and return them as a [`HashMap`](std::collections::HashMap).
-	pub fn get_all(&self) -> Result<HashMap<String, String>, String> {
-		// this could probably be improved
+	/// Delete all keys in the database.
+	pub fn empty(&self) -> Result<()> {
+		let keys = self.list()?;
 
-		let mut out = HashMap::new();
+		self.delete_listed(keys)
+	}
+
+	/// Delete all keys that start with the specified prefix, returning the number of keys deleted.
+	///
+	/// Use [`delete_prefix_count`](Self::delete_prefix_count) to find out how many keys would be deleted first.
+	pub fn delete_prefix(&self, prefix: impl Into<String>) -> Result<usize> {
+		let keys = self.list_prefix(prefix)?;
+		let count = keys.len();
+
+		self.delete_listed(keys)?;
+
+		Ok(count)
+	}
+
+	/// Count the keys that [`delete_prefix`](Self::delete_prefix) would delete, without deleting them.
+	pub fn delete_prefix_count(&self, prefix: impl Into<String>) -> Result<usize> {
+		Ok(self.list_prefix(prefix)?.len())
+	}
+
+	/// Delete all keys in the database, returning the number of keys deleted, but only if
+	/// `confirmation` is exactly [`EMPTY_CONFIRMATION`](crate::EMPTY_CONFIRMATION) (`"DELETE ALL"`).
+	///
+	/// Returns [`Error::ConfirmationRequired`] without deleting anything otherwise.
+	pub fn empty_confirmed(&self, confirmation: &str) -> Result<usize> {
+		if confirmation != EMPTY_CONFIRMATION {
+			return Err(Error::ConfirmationRequired);
+		}
 
 		let keys = self.list()?;
-		for key in keys {
-			let value = self.get(key.clone())?;
-			out.insert(key, value.unwrap());
+		let count = keys.len();
+
+		self.delete_listed(keys)?;
+
+		Ok(count)
+	}
+
+	/// Delete all keys in the database except those in `keep`, returning the number of keys deleted.
+	pub fn empty_except(&self, keep: &HashSet<String>) -> Result<usize> {
+		let mut deleted = 0;
+
+		let keys = self.list()?;
+		for key in keys.into_iter().filter(|key| !keep.contains(key)) {
+			self.delete(key)?;
+			deleted += 1;
 		}
 
-		Ok(out)
+		Ok(deleted)
 	}
-}
 
-impl Default for Client {
-	fn default() -> Self {
+	/// Check the connection to the database by reading, then writing and deleting a temporary key.
+	///
+	/// Failures are reported as `false` fields of the [`CheckResult`]. Returns an error only if
+	/// the temporary key was written but could not be deleted afterwards.
+	pub fn check(&self) -> Result<CheckResult> {
+		let mut result = CheckResult::default();
+
+		let request = self.client.get(self.url.clone())
+			.query(&[ ("encode", "true"), ("prefix", CHECK_KEY) ]);
+
+		let response = match self.send(request, None) {
+			Ok(response) => response,
+			Err(_) => return Ok(result),
+		};
+
+		result.reachable = true;
+		result.authenticated = response.status().is_success() || response.status().as_u16() == 404;
+
+		if result.authenticated && self.set(CHECK_KEY, "").is_ok() {
+			result.writable = true;
+			self.delete(CHECK_KEY)?;
+		}
+
+		Ok(result)
+	}
+
+	/// Compute a checksum of every key-value pair, which changes whenever any key or value does.
+	///
+	/// The checksum is the first 8 bytes of a SHA-256 digest over the pairs sorted by key, so it
+	/// is the same across runs, platforms and versions of this crate. Every value is downloaded,
+	/// as with [`get_all`](Self::get_all).
+	pub fn checksum(&self) -> Result<u64> {
+		Ok(checksum_pairs(&self.get_all()?))
+	}
+
+	/// Get all key-value pairs and return them as a [`HashMap`](std::collections::HashMap).
+	///
+	/// If [`max_total_bytes`](Self::max_total_bytes) is set, the sizes of all values are checked first.
+	/// Keys deleted between listing and fetching their values are left out.
+	pub fn get_all(&self) -> Result<HashMap<String, String>> {
+		self.get_all_as(|value| value)
+	}
+
+	/// Get all key-value pairs, mapping each value through `f`, and return them as a [`HashMap`](std::collections::HashMap).
+	pub fn get_all_as<T, F: Fn(String) -> T>(
+		&self,
+		f: F,
+	) -> Result<HashMap<String, T>> {
+		self.collect_all(f, |_, _| {})
+	}
+
+	/// Get all key-value pairs, keeping the outcome of each value fetch separately.
+	///
+	/// Only a failure to list the keys is returned as an error; a failure to fetch a value is stored
+	/// under its key, so the other values can still be used. Keys deleted before their value is
+	/// fetched are left out.
+	pub fn get_all_results(&self) -> Result<HashMap<String, Result<String>>> {
+		let mut out = HashMap::new();
+
+		let keys = self.list()?;
+		for key in keys {
+			match self.get(key.clone()) {
+				Ok(Some(value)) => {
+					out.insert(key, Ok(value));
+				},
+				Ok(None) => {},
+				Err(err) => {
+					out.insert(key, Err(err));
+				},
+			}
+		}
+
+		Ok(out)
+	}
+
+	/// Get all key-value pairs, calling `progress` with the number of values fetched so far and the
+	/// total number of keys, once before the first value is fetched and again after each one.
+	pub fn get_all_with_progress(
+		&self,
+		progress: impl Fn(usize, usize),
+	) -> Result<HashMap<String, String>> {
+		self.collect_all(|value| value, progress)
+	}
+
+	/// Write the value of every key to its own file in `dir`, returning the number of files written.
+	///
+	/// The directory is created if it does not exist. Each file is named after its key, with
+	/// characters that are not safe in file names percent-encoded, so that `a/b` is written to
+	/// `a%2Fb`. Existing files with the same names are overwritten.
+	pub fn export_to_dir(&self, dir: impl AsRef<Path>) -> Result<usize> {
+		let dir = dir.as_ref();
+		fs::create_dir_all(dir)?;
+
+		let mut count = 0;
+
+		let keys = self.list()?;
+		for key in keys {
+			if let Some(value) = self.get_bytes(key.clone())? {
+				fs::write(dir.join(key_to_file_name(&key)), value)?;
+				count += 1;
+			}
+		}
+
+		Ok(count)
+	}
+
+	/// Set a key for every file in `dir` to the file's contents, returning the number of keys set.
+	///
+	/// Keys are named after the files, reversing the encoding used by [`export_to_dir`](Self::export_to_dir).
+	/// Unless `overwrite` is set, keys that already exist are left untouched. Subdirectories and
+	/// files whose names are not valid UTF-8 are skipped. Keys with reserved prefixes are imported
+	/// like any other, so that an exported database, including its expiry index, is restored intact.
+	pub fn import_from_dir(
+		&self,
+		dir: impl AsRef<Path>,
+		overwrite: bool,
+	) -> Result<usize> {
+		let mut count = 0;
+
+		for entry in fs::read_dir(dir)? {
+			let entry = entry?;
+			if !entry.file_type()?.is_file() {
+				continue;
+			}
+
+			let key = match entry.file_name().into_string() {
+				Ok(name) => file_name_to_key(&name)?,
+				Err(_) => continue,
+			};
+
+			if !overwrite && self.exists(key.as_str())? {
+				continue;
+			}
+
+			self.set_unchecked(key, fs::read_to_string(entry.path())?)?;
+			count += 1;
+		}
+
+		Ok(count)
+	}
+
+	fn collect_all<T>(
+		&self,
+		f: impl Fn(String) -> T,
+		progress: impl Fn(usize, usize),
+	) -> Result<HashMap<String, T>> {
+		// this could probably be improved
+
+		let mut out = HashMap::new();
+
+		let keys = self.list()?;
+		if let Some(limit) = self.options.max_total_bytes {
+			self.check_total_size(&keys, limit)?;
+		}
+
+		let total = keys.len();
+		progress(0, total);
+
+		for (index, key) in keys.into_iter().enumerate() {
+			// keys deleted since listing are skipped
+			if let Some(value) = self.get(key.clone())? {
+				out.insert(key, f(value));
+			}
+			progress(index + 1, total);
+		}
+
+		Ok(out)
+	}
+
+	fn delete_listed(&self, keys: Vec<String>) -> Result<()> {
+		if !self.options.best_effort_deletes {
+			return self.delete_many(keys);
+		}
+
+		let mut failed = Vec::new();
+		for key in keys {
+			if let Err(err) = self.delete(key.as_str()) {
+				failed.push((key, err));
+			}
+		}
+
+		if failed.is_empty() {
+			Ok(())
+		} else {
+			Err(Error::DeleteFailed(failed))
+		}
+	}
+
+	fn get_response(&self, key: String) -> Result<Option<Response>> {
+		let encoded_key = encode(&self.options.storage_key(&key)?).into_owned();
+
+		let mut request = self.client.get(format!("{}/{}", self.url, encoded_key));
+		if let Some(accept) = &self.options.accept {
+			request = request.header("Accept", accept);
+		}
+
+		let response = self.send(request, Some(&key))?;
+
+		if response.status().is_success() {
+			Ok(Some(response))
+		} else if response.status().as_u16() == 404 {
+			Ok(None)
+		} else {
+			Err(server_error(response))
+		}
+	}
+
+	fn read_body(&self, response: Response) -> Result<Vec<u8>> {
+		let limit = match self.options.max_value_read_bytes {
+			Some(limit) => limit,
+			None => return Ok(response.bytes()?.to_vec()),
+		};
+
+		if content_length(response.headers()).is_some_and(|length| length > limit) {
+			return Err(Error::ValueTooLarge { limit });
+		}
+
+		let mut body = Vec::new();
+		response.take(limit + 1).read_to_end(&mut body)?;
+
+		if body.len() as u64 > limit {
+			return Err(Error::ValueTooLarge { limit });
+		}
+
+		Ok(body)
+	}
+
+	fn check_total_size(&self, keys: &[String], limit: u64) -> Result<()> {
+		let mut size = 0;
+
+		for key in keys {
+			let request = self.client.head(format!("{}/{}", self.url, encode(&self.options.storage_key(key)?)));
+			let response = self.send(request, Some(key))?;
+
+			if response.status().as_u16() == 404 {
+				continue;
+			}
+			if !response.status().is_success() {
+				return Err(server_error(response));
+			}
+
+			size += content_length(response.headers()).unwrap_or(0);
+			if size > limit {
+				return Err(Error::TooLarge { size, limit });
+			}
+		}
+
+		Ok(())
+	}
+
+	fn set_chunks(&self, chunks: Vec<String>) -> Result<()> {
+		for body in chunks {
+			let request = self.post(body);
+
+			let response = self.send(request, None)?;
+
+			if !response.status().is_success() {
+				return Err(server_error(response));
+			}
+		}
+
+		Ok(())
+	}
+
+	fn set_unchecked(&self, key: String, value: impl Into<String>) -> Result<()> {
+		let encoded_key = encode(&self.options.storage_key(&key)?).into_owned();
+		let encoded_value = encode(value.into().as_str()).into_owned();
+
+		let request = self.post(format!("{}={}", encoded_key, encoded_value));
+
+		let response = self.send(request, Some(&key))?;
+
+		if response.status().is_success() {
+			self.options.invalidate(&key);
+			Ok(())
+		} else {
+			Err(server_error(response))
+		}
+	}
+
+	fn post(&self, body: String) -> RequestBuilder {
+		let request = self.client.post(self.url.clone())
+			.body(body)
+			.header("Content-Type", "application/x-www-form-urlencoded");
+
+		if self.options.idempotency_keys {
+			request.header(IDEMPOTENCY_KEY_HEADER, next_idempotency_key())
+		} else {
+			request
+		}
+	}
+
+	fn send(
+		&self,
+		mut request: RequestBuilder,
+		key: Option<&str>,
+	) -> Result<Response> {
+		let id = next_request_id();
+		if self.options.request_id_header {
+			request = request.header(REQUEST_ID_HEADER, id.as_str());
+		}
+		if let Some(token) = &self.options.bearer_token {
+			request = request.bearer_auth(token);
+		}
+
+		#[cfg(feature = "tracing")]
+		let _span = tracing::debug_span!("request", id = %id).entered();
+
+		let mut request = request.build()?;
+		let idempotent = request.method() != Method::POST;
+
+		let mut attempt = 0;
+		loop {
+			let retry = if attempt < self.options.retries { request.try_clone() } else { None };
+			let result = self.execute(request, key);
+
+			let next = match retry {
+				Some(next) => next,
+				None => return result,
+			};
+
+			// A rate-limited request was not applied, so even writes can be retried
+			let delay = match &result {
+				Ok(response) if response.status().as_u16() == 429 => retry_after(response.headers()),
+				Ok(response) if idempotent && response.status().is_server_error() => None,
+				Err(err) if is_transient(err, idempotent) => None,
+				_ => return result,
+			};
+
+			thread::sleep(delay.unwrap_or_else(|| self.options.retry_delay(attempt)));
+			request = next;
+			attempt += 1;
+		}
+	}
+
+	fn execute(
+		&self,
+		request: Request,
+		key: Option<&str>,
+	) -> Result<Response> {
+		let hook = match &self.options.inspect {
+			Some(hook) => hook,
+			None => return Ok(self.client.execute(request)?),
+		};
+
+		let request_info = RequestInfo {
+			method: request.method().clone(),
+			key: key.map(String::from),
+			body_len: request.body().and_then(|body| body.as_bytes()).map_or(0, <[u8]>::len),
+		};
+
+		let start = Instant::now();
+		let result = self.client.execute(request);
+
+		let response_info = ResponseInfo {
+			status: result.as_ref().ok().map(|response| response.status().as_u16()),
+			content_length: result.as_ref().ok().and_then(|response| response.content_length()),
+			elapsed: start.elapsed(),
+		};
+		(hook.0)(&request_info, &response_info);
+
+		Ok(result?)
+	}
+}
+
+impl Default for Client {
+	fn default() -> Self {
 		Self::new()
 	}
 }
+
+fn server_error(response: Response) -> Error {
+	let status = response.status().as_u16();
+
+	match response.text() {
+		Ok(body) => Error::Server { status, body },
+		Err(err) => err.into(),
+	}
+}
+
+fn build_http_client(options: &Options) -> Result<HttpClient> {
+	let timeout = match (options.timeout, options.read_timeout) {
+		(Some(timeout), Some(read_timeout)) => Some(timeout.min(read_timeout)),
+		(timeout, read_timeout) => timeout.or(read_timeout),
+	};
+
+	// Leave unset timeouts to the builder, as the blocking client has a default request timeout
+	let mut builder = HttpClient::builder();
+	if let Some(timeout) = timeout {
+		builder = builder.timeout(timeout);
+	}
+	if let Some(timeout) = options.connect_timeout {
+		builder = builder.connect_timeout(timeout);
+	}
+
+	Ok(builder.build()?)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::mock::{ MockResponse, MockServer };
+
+	use std::time::UNIX_EPOCH;
+
+	#[test]
+	fn try_new_url_accepts_valid_url() {
+		assert!(Client::try_new_url("https://kv.replit.com/v0/token").is_ok());
+	}
+
+	#[test]
+	fn try_new_url_rejects_invalid_url() {
+		assert!(matches!(Client::try_new_url("not a url"), Err(Error::InvalidUrl(_))));
+	}
+
+	fn check_with_read_status(status: u16) -> CheckResult {
+		let server = MockServer::with_handler(move |request, _| match (request.method.as_str(), &request.key) {
+			("GET", None) => Some(MockResponse::status(status)),
+			_ => None,
+		});
+
+		Client::new_url(server.url()).check().unwrap()
+	}
+
+	#[test]
+	fn check_maps_read_status() {
+		let all = CheckResult { reachable: true, authenticated: true, writable: true };
+		let unauthenticated = CheckResult { reachable: true, authenticated: false, writable: false };
+
+		assert_eq!(check_with_read_status(200), all);
+		assert_eq!(check_with_read_status(401), unauthenticated);
+		assert_eq!(check_with_read_status(500), unauthenticated);
+	}
+
+	#[test]
+	fn set_rejects_reserved_key() {
+		let client = Client::new_url("http://127.0.0.1:9");
+
+		assert!(matches!(client.set("__meta__:foo", "bar"), Err(Error::ReservedKey(key)) if key == "__meta__:foo"));
+	}
+
+	#[test]
+	fn empty_confirmed_requires_confirmation() {
+		let server = MockServer::start();
+		server.insert("a", "1");
+		server.insert("b", "2");
+		let client = Client::new_url(server.url());
+
+		assert!(matches!(client.empty_confirmed("delete all"), Err(Error::ConfirmationRequired)));
+		assert_eq!(server.values().len(), 2);
+
+		assert_eq!(client.empty_confirmed(EMPTY_CONFIRMATION).unwrap(), 2);
+		assert!(server.values().is_empty());
+	}
+
+	#[test]
+	fn rejects_invalid_keys_without_requests() {
+		let server = MockServer::start();
+		let client = Client::new_url(server.url());
+
+		for key in &[ "", "a\nb" ] {
+			assert!(matches!(client.get(*key), Err(Error::InvalidKey(_))));
+			assert!(matches!(client.set(*key, "value"), Err(Error::InvalidKey(_))));
+			assert!(matches!(client.delete(*key), Err(Error::InvalidKey(_))));
+		}
+
+		assert!(server.requests().is_empty());
+	}
+
+	#[test]
+	fn set_many_writes_last_duplicate_in_one_request() {
+		let server = MockServer::start();
+		let client = Client::new_url(server.url());
+
+		client.set_many(vec![ ("a", "1"), ("a", "2") ]).unwrap();
+
+		assert_eq!(server.requests().len(), 1);
+		assert_eq!(server.values()["a"], "2");
+	}
+
+	#[test]
+	fn set_many_splits_into_chunks() {
+		let server = MockServer::start();
+		let client = Client::new_url(server.url()).set_many_chunk_bytes(16);
+
+		let pairs = (0..10).map(|index| (format!("key{}", index), "value".to_string()));
+		client.set_many(pairs).unwrap();
+
+		assert!(server.requests().len() > 1);
+		assert!(server.requests().iter().all(|request| request.body.len() <= 16));
+		assert_eq!(server.values().len(), 10);
+	}
+
+	#[test]
+	fn checksum_changes_after_mutation() {
+		let server = MockServer::start();
+		server.insert("a", "1");
+		let client = Client::new_url(server.url());
+
+		let before = client.checksum().unwrap();
+		assert_eq!(client.checksum().unwrap(), before);
+
+		client.set("a", "2").unwrap();
+		assert_ne!(client.checksum().unwrap(), before);
+	}
+
+	#[test]
+	fn get_rejects_invalid_utf8() {
+		let server = MockServer::start();
+		server.insert("a", vec![ b'o', b'k', 0xff ]);
+		let client = Client::new_url(server.url());
+
+		assert!(matches!(client.get("a"), Err(Error::Decode(_))));
+		assert_eq!(client.get_lossy("a").unwrap().as_deref(), Some("ok\u{fffd}"));
+	}
+
+	#[test]
+	fn get_all_checks_total_size_before_fetching() {
+		let server = MockServer::start();
+		server.insert("a", "0123456789");
+		server.insert("b", "0123456789");
+		let client = Client::new_url(server.url()).max_total_bytes(15);
+
+		assert!(matches!(client.get_all(), Err(Error::TooLarge { size: 20, limit: 15 })));
+		assert!(server.requests().iter().all(|request| request.method != "GET" || request.key.is_none()));
+	}
+
+	#[test]
+	fn get_all_size_check_reports_server_errors() {
+		let server = MockServer::with_handler(|request, _| match request.method.as_str() {
+			"HEAD" => Some(MockResponse::status(500).body("broken")),
+			_ => None,
+		});
+		server.insert("a", "1");
+		let client = Client::new_url(server.url()).max_total_bytes(15);
+
+		assert!(matches!(client.get_all(), Err(Error::Server { status: 500, .. })));
+	}
+
+	#[test]
+	fn get_enforces_read_limit() {
+		let server = MockServer::start();
+		server.insert("small", "0123");
+		server.insert("large", "0123456789");
+		let client = Client::new_url(server.url()).max_value_read_bytes(4);
+
+		assert_eq!(client.get("small").unwrap().as_deref(), Some("0123"));
+		assert!(matches!(client.get("large"), Err(Error::ValueTooLarge { limit: 4 })));
+		assert!(matches!(client.get_bytes("large"), Err(Error::ValueTooLarge { limit: 4 })));
+	}
+
+	fn failing_twice() -> MockServer {
+		let failures = std::sync::atomic::AtomicUsize::new(0);
+
+		MockServer::with_handler(move |_, _| {
+			if failures.fetch_add(1, std::sync::atomic::Ordering::SeqCst) < 2 {
+				Some(MockResponse::status(503))
+			} else {
+				None
+			}
+		})
+	}
+
+	#[test]
+	fn retries_reads_after_server_errors() {
+		let server = failing_twice();
+		server.insert("a", "1");
+		let client = Client::new_url(server.url())
+			.retries(2)
+			.retry_backoff(Duration::from_millis(1));
+
+		assert_eq!(client.get("a").unwrap().as_deref(), Some("1"));
+		assert_eq!(server.requests().len(), 3);
+	}
+
+	#[test]
+	fn does_not_retry_writes_after_a_response() {
+		let server = failing_twice();
+		let client = Client::new_url(server.url())
+			.retries(2)
+			.retry_backoff(Duration::from_millis(1));
+
+		assert!(matches!(client.set("a", "1"), Err(Error::Server { status: 503, .. })));
+		assert_eq!(server.requests().len(), 1);
+	}
+
+	#[test]
+	fn best_effort_empty_reports_failed_deletes() {
+		let server = MockServer::with_handler(|request, _| {
+			if request.method == "DELETE" && request.key.as_deref() == Some("stuck") {
+				Some(MockResponse::status(500))
+			} else {
+				None
+			}
+		});
+		for key in &[ "a", "stuck", "b" ] {
+			server.insert(key, "1");
+		}
+		let client = Client::new_url(server.url()).best_effort_deletes(true);
+
+		match client.empty() {
+			Err(Error::DeleteFailed(failed)) => {
+				assert_eq!(failed.len(), 1);
+				assert_eq!(failed[0].0, "stuck");
+			},
+			other => panic!("unexpected result: {:?}", other),
+		}
+		assert_eq!(server.values().keys().collect::<Vec<&String>>(), vec![ "stuck" ]);
+	}
+
+	#[test]
+	fn key_transform_round_trips_listed_keys() {
+		let server = MockServer::start();
+		server.insert("prod:a", "1");
+		server.insert("prod:b", "2");
+		server.insert("dev:a", "3");
+		let client = Client::new_url(server.url()).key_transform(
+			|key| format!("prod:{}", key),
+			|key| key.strip_prefix("prod:").unwrap_or(key).to_string(),
+		);
+
+		assert_eq!(client.list().unwrap(), vec![ "a", "b" ]);
+		assert_eq!(client.get("a").unwrap().as_deref(), Some("1"));
+
+		let all = client.get_all().unwrap();
+		assert_eq!(all.len(), 2);
+		assert_eq!(all["b"], "2");
+
+		client.empty().unwrap();
+		assert_eq!(server.values().keys().collect::<Vec<&String>>(), vec![ "dev:a" ]);
+	}
+
+	#[test]
+	fn rename_moves_value() {
+		let server = MockServer::start();
+		server.insert("a", "1");
+		let client = Client::new_url(server.url());
+
+		client.rename("a", "b").unwrap();
+		assert_eq!(server.values().into_iter().collect::<Vec<(String, String)>>(), vec![ ("b".to_string(), "1".to_string()) ]);
+	}
+
+	#[test]
+	fn rename_reports_missing_source() {
+		let server = MockServer::start();
+		server.insert("b", "1");
+		let client = Client::new_url(server.url());
+
+		assert!(matches!(client.rename("a", "b"), Err(Error::MissingKey(key)) if key == "a"));
+		assert_eq!(server.values()["b"], "1");
+	}
+
+	#[test]
+	fn rename_to_itself_keeps_value() {
+		let server = MockServer::start();
+		server.insert("a", "1");
+		let client = Client::new_url(server.url());
+
+		client.rename("a", "a").unwrap();
+		assert_eq!(server.values()["a"], "1");
+	}
+
+	#[test]
+	fn sweep_expired_deletes_only_due_keys() {
+		let server = MockServer::start();
+		let client = Client::new_url(server.url()).hash_long_keys(true);
+		let long_key = "k".repeat(2000);
+
+		client.set_with_ttl("due", "1", Duration::ZERO).unwrap();
+		client.set_with_ttl(long_key.as_str(), "2", Duration::ZERO).unwrap();
+		client.set_with_ttl("later", "3", Duration::from_secs(3600)).unwrap();
+		assert_eq!(server.values().len(), 6);
+
+		assert_eq!(client.sweep_expired().unwrap(), 2);
+
+		let values = server.values();
+		assert_eq!(values.len(), 2);
+		assert_eq!(values["later"], "3");
+		assert!(values.iter().any(|(key, value)| key.starts_with(EXPIRY_PREFIX) && value == "later"));
+	}
+
+	#[test]
+	fn export_and_import_round_trip() {
+		let source = MockServer::start();
+		for &(key, value) in &[ ("a/b", "1"), ("a.b", "2"), ("plain", "3"), ("__exp__:00000000000000000001:x", "x") ] {
+			source.insert(key, value);
+		}
+		let destination = MockServer::start();
+		let dir = std::env::temp_dir().join(format!("replitdb-sync-export-{}", std::process::id()));
+
+		assert_eq!(Client::new_url(source.url()).export_to_dir(&dir).unwrap(), 4);
+		let imported = Client::new_url(destination.url()).import_from_dir(&dir, false);
+		std::fs::remove_dir_all(&dir).unwrap();
+
+		assert_eq!(imported.unwrap(), 4);
+		assert_eq!(destination.values(), source.values());
+	}
+
+	#[test]
+	fn empty_listing_has_no_keys() {
+		let server = MockServer::start();
+		let client = Client::new_url(server.url());
+
+		assert_eq!(client.list().unwrap(), Vec::<String>::new());
+		assert_eq!(client.delete_prefix_count("a").unwrap(), 0);
+		assert_eq!(client.delete_prefix("a").unwrap(), 0);
+	}
+
+	#[test]
+	fn delete_prefix_counts_deleted_keys() {
+		let server = MockServer::start();
+		for key in &[ "a1", "a2", "b1" ] {
+			server.insert(key, "1");
+		}
+		let client = Client::new_url(server.url());
+
+		assert_eq!(client.delete_prefix_count("a").unwrap(), 2);
+		assert_eq!(client.delete_prefix("a").unwrap(), 2);
+		assert_eq!(server.values().keys().collect::<Vec<&String>>(), vec![ "b1" ]);
+	}
+
+	/// Generate strings of up to `max_len` characters from a mix of characters that need
+	/// encoding, control characters and arbitrary code points, using a fixed-seed LCG.
+	fn arbitrary_string(seed: &mut u64, max_len: usize, newlines: bool) -> String {
+		const SPECIAL: &[char] = &[
+			' ', '%', '+', '&', '=', '?', '#', '/', '\\', '.', ':', ';', '"', '\'', '<', '>', '~',
+			'\0', '\t', '\r', '\u{7f}', '\u{85}', '\u{a0}', '\u{feff}', '\u{fffd}', 'é', '日', '\u{1f600}',
+		];
+
+		let mut next = || {
+			*seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+			(*seed >> 33) as u32
+		};
+
+		let len = next() as usize % (max_len + 1);
+		(0..len)
+			.map(|_| match next() % 4 {
+				0 => SPECIAL[next() as usize % SPECIAL.len()],
+				1 => char::from_u32(next() % 0x20).filter(|&c| newlines || c != '\n').unwrap_or('a'),
+				2 => char::from_u32(next() % 0x11_0000).unwrap_or('\u{10ffff}'),
+				_ => char::from(b'a' + (next() % 26) as u8),
+			})
+			.collect()
+	}
+
+	#[test]
+	fn arbitrary_keys_and_values_round_trip() {
+		let server = MockServer::start();
+		let client = Client::new_url(server.url());
+		let mut seed = 242;
+
+		for _ in 0..200 {
+			let key = arbitrary_string(&mut seed, 24, false);
+			let value = arbitrary_string(&mut seed, 64, true);
+
+			match client.set(key.as_str(), value.as_str()) {
+				Err(Error::InvalidKey(_)) | Err(Error::ReservedKey(_)) => continue,
+				result => result.unwrap(),
+			}
+
+			assert_eq!(client.get(key.as_str()).unwrap().as_ref(), Some(&value), "value of {:?}", key);
+			assert!(client.list().unwrap().contains(&key), "listing {:?}", key);
+			assert!(client.list_prefix(key.as_str()).unwrap().contains(&key), "listing prefix {:?}", key);
+		}
+	}
+
+	#[test]
+	fn edge_case_keys_round_trip() {
+		let server = MockServer::start();
+		let client = Client::new_url(server.url());
+
+		for &key in &[ ".", ".." ] {
+			assert!(matches!(client.set(key, "1"), Err(Error::InvalidKey(_))), "key {:?}", key);
+			assert!(matches!(client.get(key), Err(Error::InvalidKey(_))), "key {:?}", key);
+		}
+
+		for &key in &[ "...", "./", "a/..", " ", "+", "%", "%2E%2E", "a=b&c", "?#", "\r", "\0" ] {
+			client.set(key, key).unwrap();
+
+			assert_eq!(client.get(key).unwrap().as_deref(), Some(key), "value of {:?}", key);
+			assert!(client.list().unwrap().iter().any(|listed| listed == key), "listing {:?}", key);
+		}
+	}
+
+	#[test]
+	fn arbitrary_keys_round_trip_through_file_names() {
+		let mut seed = 242;
+
+		for _ in 0..1000 {
+			let key = arbitrary_string(&mut seed, 24, true);
+			let name = key_to_file_name(&key);
+
+			assert!(!name.contains('/') && name != "." && name != "..", "file name {:?}", name);
+			assert_eq!(file_name_to_key(&name).unwrap(), key);
+		}
+	}
+
+	#[test]
+	fn set_prefix_changes_namespace() {
+		let server = MockServer::start();
+		server.insert("other", "0");
+		let mut client = Client::new_url(server.url());
+
+		client.set_prefix(Some("app:".to_string())).unwrap();
+		client.set("a", "1").unwrap();
+		assert_eq!(client.list().unwrap(), vec![ "a" ]);
+		assert_eq!(client.get("a").unwrap().as_deref(), Some("1"));
+
+		client.set_prefix(None).unwrap();
+		assert_eq!(client.list().unwrap(), vec![ "app:a", "other" ]);
+		assert_eq!(client.get("a").unwrap(), None);
+	}
+
+	#[test]
+	fn long_keys_are_hashed_inside_the_namespace() {
+		let server = MockServer::start();
+		server.insert("dev:a", "0");
+		let long_key = "k".repeat(2000);
+
+		let client = Client::new_url(server.url())
+			.hash_long_keys(true)
+			.key_transform(
+				|key| format!("prod:{}", key),
+				|key| key.strip_prefix("prod:").unwrap_or(key).to_string(),
+			);
+		client.set(long_key.as_str(), "1").unwrap();
+		assert!(server.values().keys().any(|key| key.starts_with("prod:__hash__:")));
+		assert_eq!(client.get(long_key.as_str()).unwrap().as_deref(), Some("1"));
+
+		let mut client = Client::new_url(server.url()).hash_long_keys(true);
+		client.set_prefix(Some("app:".to_string())).unwrap();
+		client.set(long_key.as_str(), "2").unwrap();
+		assert!(server.values().keys().any(|key| key.starts_with("app:__hash__:")));
+		assert_eq!(client.list().unwrap().len(), 1);
+
+		client.empty().unwrap();
+		assert_eq!(server.values().len(), 2);
+		assert!(server.values().keys().all(|key| !key.starts_with("app:")));
+
+		let client = Client::new_url(server.url())
+			.hash_long_keys(true)
+			.key_transform(
+				|key| format!("prod:{}", key),
+				|key| key.strip_prefix("prod:").unwrap_or(key).to_string(),
+			);
+		client.empty().unwrap();
+		assert_eq!(server.values().keys().collect::<Vec<&String>>(), vec![ "dev:a" ]);
+	}
+
+	#[test]
+	fn sweep_expired_follows_extended_ttl() {
+		let server = MockServer::start();
+		let client = Client::new_url(server.url());
+
+		client.set_with_ttl("k", "1", Duration::ZERO).unwrap();
+		client.set_with_ttl("k", "2", Duration::from_secs(86400)).unwrap();
+		assert_eq!(server.values().len(), 3);
+
+		assert_eq!(client.sweep_expired().unwrap(), 0);
+
+		let values = server.values();
+		assert_eq!(values.len(), 2);
+		assert_eq!(values["k"], "2");
+	}
+
+	#[test]
+	fn retries_rate_limited_requests_after_retry_after() {
+		let server = MockServer::with_handler(|request, state| {
+			if state.requests.iter().filter(|seen| seen.method == request.method).count() == 1 {
+				Some(MockResponse::status(429).header("Retry-After", "2"))
+			} else {
+				None
+			}
+		});
+		let client = Client::new_url(server.url()).retries(1);
+
+		let start = Instant::now();
+		client.set("a", "1").unwrap();
+		assert!(start.elapsed() >= Duration::from_secs(2));
+
+		let start = Instant::now();
+		assert_eq!(client.get("a").unwrap().as_deref(), Some("1"));
+		assert!(start.elapsed() >= Duration::from_secs(2));
+		assert_eq!(server.requests().len(), 4);
+	}
+
+	#[test]
+	fn get_all_skips_keys_deleted_after_listing() {
+		let server = MockServer::with_handler(|request, state| match (request.method.as_str(), &request.key) {
+			("GET", Some(key)) if key == "b" => {
+				state.values.remove("b");
+				Some(MockResponse::status(404))
+			},
+			_ => None,
+		});
+		for key in &[ "a", "b", "c" ] {
+			server.insert(key, "1");
+		}
+		let client = Client::new_url(server.url());
+
+		let all = client.get_all().unwrap();
+		let mut keys = all.keys().collect::<Vec<&String>>();
+		keys.sort();
+		assert_eq!(keys, vec![ "a", "c" ]);
+	}
+
+	#[test]
+	fn exists_checks_with_head() {
+		let server = MockServer::start();
+		server.insert("present", "value");
+		let client = Client::new_url(server.url());
+
+		assert!(client.exists("present").unwrap());
+		assert!(!client.exists("absent").unwrap());
+		assert!(server.requests().iter().all(|request| request.method == "HEAD"));
+	}
+
+	#[test]
+	fn exists_falls_back_to_get_without_head() {
+		let server = MockServer::with_handler(|request, _| {
+			if request.method == "HEAD" {
+				Some(MockResponse::status(405))
+			} else {
+				None
+			}
+		});
+		server.insert("present", "value");
+		let client = Client::new_url(server.url());
+
+		assert!(client.exists("present").unwrap());
+		assert!(!client.exists("absent").unwrap());
+
+		let methods = server.requests().into_iter().map(|request| request.method).collect::<Vec<String>>();
+		assert_eq!(methods, vec![ "HEAD", "GET", "HEAD", "GET" ]);
+	}
+
+	#[cfg(feature = "json")]
+	#[derive(Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+	struct Point {
+		x: i32,
+		label: String,
+	}
+
+	#[cfg(feature = "json")]
+	#[test]
+	fn json_round_trips_a_struct() {
+		let server = MockServer::start();
+		let client = Client::new_url(server.url());
+		let point = Point { x: 3, label: "a & b".to_string() };
+
+		client.set_json("point", &point).unwrap();
+		assert_eq!(server.values()["point"], r#"{"x":3,"label":"a & b"}"#);
+		assert_eq!(client.get_json::<Point>("point").unwrap(), Some(point));
+	}
+
+	#[cfg(feature = "json")]
+	#[test]
+	fn get_json_reports_invalid_and_missing_values() {
+		let server = MockServer::start();
+		server.insert("point", "not json");
+		let client = Client::new_url(server.url());
+
+		assert!(matches!(client.get_json::<Point>("point"), Err(Error::Json(_))));
+		assert_eq!(client.get_json::<Point>("missing").unwrap(), None);
+	}
+
+	#[test]
+	fn connect_timeout_fails_fast_on_unroutable_address() {
+		let client = Client::new_url("http://10.255.255.1")
+			.connect_timeout(Duration::from_millis(200))
+			.read_timeout(Duration::from_secs(5));
+
+		let start = Instant::now();
+		assert!(matches!(client.get("a"), Err(Error::Http(_))));
+		assert!(start.elapsed() < Duration::from_secs(3));
+	}
+
+	#[test]
+	fn read_timeout_fires_on_slow_response() {
+		let server = MockServer::start();
+		server.insert("a", "1");
+		server.set_delay(Duration::from_millis(500));
+		let client = Client::new_url(server.url())
+			.connect_timeout(Duration::from_secs(1))
+			.read_timeout(Duration::from_millis(100));
+
+		assert!(matches!(client.get("a"), Err(Error::Http(err)) if err.is_timeout()));
+	}
+
+	#[test]
+	fn retries_reads_after_connection_reset() {
+		let server = MockServer::with_handler(|_, state| {
+			if state.requests.len() == 1 {
+				Some(MockResponse::reset())
+			} else {
+				None
+			}
+		});
+		server.insert("a", "1");
+		let client = Client::new_url(server.url())
+			.retries(1)
+			.retry_backoff(Duration::from_millis(1));
+
+		assert_eq!(client.get("a").unwrap().as_deref(), Some("1"));
+		assert_eq!(server.requests().len(), 2);
+	}
+
+	fn conditional_server() -> MockServer {
+		// Keys are taken to have been modified at the Unix epoch plus 1000 seconds
+		MockServer::with_handler(|request, _| {
+			let since = httpdate::parse_http_date(request.header("If-Unmodified-Since")?).unwrap();
+
+			if since < UNIX_EPOCH + Duration::from_secs(1000) {
+				Some(MockResponse::status(412))
+			} else {
+				None
+			}
+		})
+	}
+
+	#[test]
+	fn set_if_unmodified_since_writes_when_unmodified() {
+		let server = conditional_server();
+		let client = Client::new_url(server.url());
+		let since = UNIX_EPOCH + Duration::from_secs(2000);
+
+		client.set_if_unmodified_since("a", "1", since).unwrap();
+		assert_eq!(server.values()["a"], "1");
+		assert_eq!(server.requests()[0].header("If-Unmodified-Since"), Some("Thu, 01 Jan 1970 00:33:20 GMT"));
+	}
+
+	#[test]
+	fn set_if_unmodified_since_maps_precondition_failed() {
+		let server = conditional_server();
+		let client = Client::new_url(server.url());
+		let since = UNIX_EPOCH + Duration::from_secs(500);
+
+		assert!(matches!(client.set_if_unmodified_since("a", "1", since), Err(Error::PreconditionFailed)));
+		assert!(server.values().is_empty());
+	}
+
+	#[test]
+	fn get_all_results_keeps_other_values_after_a_failure() {
+		let server = MockServer::with_handler(|request, _| match (request.method.as_str(), &request.key) {
+			("GET", Some(key)) if key == "b" => Some(MockResponse::status(500)),
+			_ => None,
+		});
+		for key in &[ "a", "b", "c" ] {
+			server.insert(key, key.to_uppercase());
+		}
+		let client = Client::new_url(server.url());
+
+		let results = client.get_all_results().unwrap();
+		assert_eq!(results.len(), 3);
+		assert_eq!(results["a"].as_deref().unwrap(), "A");
+		assert_eq!(results["c"].as_deref().unwrap(), "C");
+		assert!(matches!(results["b"], Err(Error::Server { status: 500, .. })));
+	}
+
+	#[cfg(feature = "serde")]
+	#[test]
+	fn get_dynamic_distinguishes_json_and_text() {
+		let server = MockServer::start();
+		server.insert("json", r#"{"a":[1,2]}"#);
+		server.insert("text", "hello world");
+		let client = Client::new_url(server.url());
+
+		match client.get_dynamic("json").unwrap() {
+			Some(DbValue::Json(json)) => assert_eq!(json["a"][1], 2),
+			other => panic!("unexpected value: {:?}", other),
+		}
+		assert!(matches!(client.get_dynamic("text").unwrap(), Some(DbValue::Text(text)) if text == "hello world"));
+		assert!(client.get_dynamic("missing").unwrap().is_none());
+	}
+
+	#[cfg(feature = "cache")]
+	#[test]
+	fn set_invalidates_cached_listings_under_its_prefix() {
+		let server = MockServer::start();
+		server.insert("a1", "1");
+		server.insert("b1", "1");
+		let client = Client::new_url(server.url()).cache_lists(Duration::from_secs(60));
+		let listings = || server.requests().iter()
+			.filter(|request| request.method == "GET" && request.key.is_none())
+			.count();
+
+		assert_eq!(client.list_prefix("a").unwrap(), vec![ "a1" ]);
+		assert_eq!(client.list_prefix("a").unwrap(), vec![ "a1" ]);
+		assert_eq!(client.list_prefix("b").unwrap(), vec![ "b1" ]);
+		assert_eq!(listings(), 2);
+
+		client.set("a2", "2").unwrap();
+		assert_eq!(client.list_prefix("a").unwrap(), vec![ "a1", "a2" ]);
+		assert_eq!(client.list_prefix("b").unwrap(), vec![ "b1" ]);
+		assert_eq!(listings(), 3);
+	}
+
+	#[test]
+	fn request_id_header_is_sent_when_enabled() {
+		let server = MockServer::start();
+		Client::new_url(server.url()).get("a").unwrap();
+		let client = Client::new_url(server.url()).request_id_header(true);
+		client.get("a").unwrap();
+		client.get("a").unwrap();
+
+		let requests = server.requests();
+		assert!(requests[0].header(REQUEST_ID_HEADER).is_none());
+		let first = requests[1].header(REQUEST_ID_HEADER).unwrap();
+		let second = requests[2].header(REQUEST_ID_HEADER).unwrap();
+		assert!(first.starts_with(&format!("{}-", std::process::id())));
+		assert_ne!(first, second);
+	}
+
+	#[cfg(feature = "tracing")]
+	#[test]
+	fn request_id_header_matches_the_span_id() {
+		use std::sync::Mutex;
+		use tracing::field::{ Field, Visit };
+		use tracing::span::{ Attributes, Id, Record };
+		use tracing::{ Event, Metadata };
+
+		#[derive(Clone, Default)]
+		struct SpanIds(Arc<Mutex<Vec<String>>>);
+
+		struct IdVisitor<'a>(&'a Mutex<Vec<String>>);
+
+		impl Visit for IdVisitor<'_> {
+			fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+				if field.name() == "id" {
+					self.0.lock().unwrap().push(format!("{:?}", value));
+				}
+			}
+		}
+
+		impl tracing::Subscriber for SpanIds {
+			fn enabled(&self, _: &Metadata<'_>) -> bool {
+				true
+			}
+
+			fn new_span(&self, span: &Attributes<'_>) -> Id {
+				span.record(&mut IdVisitor(&self.0));
+				Id::from_u64(1)
+			}
+
+			fn record(&self, _: &Id, _: &Record<'_>) {}
+			fn record_follows_from(&self, _: &Id, _: &Id) {}
+			fn event(&self, _: &Event<'_>) {}
+			fn enter(&self, _: &Id) {}
+			fn exit(&self, _: &Id) {}
+		}
+
+		let server = MockServer::start();
+		let client = Client::new_url(server.url()).request_id_header(true);
+		let ids = SpanIds::default();
+		tracing::subscriber::with_default(ids.clone(), || client.get("a").unwrap());
+
+		let header = server.requests()[0].header(REQUEST_ID_HEADER).unwrap().to_string();
+		assert_eq!(*ids.0.lock().unwrap(), vec![ header ]);
+	}
+
+	#[test]
+	fn retried_writes_reuse_their_idempotency_key() {
+		let server = MockServer::with_handler(|_, state| {
+			if state.requests.len() == 1 {
+				Some(MockResponse::status(429).header("Retry-After", "0"))
+			} else {
+				None
+			}
+		});
+		let client = Client::new_url(server.url()).idempotency_keys(true).retries(1);
+		client.set("a", "1").unwrap();
+		client.set("b", "2").unwrap();
+
+		let requests = server.requests();
+		let keys: Vec<_> = requests.iter()
+			.map(|request| request.header(IDEMPOTENCY_KEY_HEADER).unwrap())
+			.collect();
+		assert_eq!(keys.len(), 3);
+		assert_eq!(keys[0], keys[1]);
+		assert_ne!(keys[1], keys[2]);
+		assert_eq!(server.values()["a"], "1");
+	}
+
+	#[test]
+	fn set_timeout_limits_each_request() {
+		let server = MockServer::start();
+		server.set_delay(Duration::from_millis(500));
+		let mut client = Client::new_url(server.url());
+
+		client.set_timeout(Some(Duration::from_millis(100))).unwrap();
+		assert!(matches!(client.get("a"), Err(Error::Http(err)) if err.is_timeout()));
+
+		client.set_timeout(None).unwrap();
+		assert!(client.get("a").unwrap().is_none());
+	}
+
+	#[test]
+	fn accept_header_is_sent_with_reads() {
+		let server = MockServer::start();
+		Client::new_url(server.url()).accept("text/plain").get("a").unwrap();
+		assert_eq!(server.requests()[0].header("Accept"), Some("text/plain"));
+	}
+
+	#[test]
+	fn bearer_token_is_sent_with_every_request() {
+		let server = MockServer::start();
+		let client = Client::new_url(server.url()).bearer_token("secret");
+		client.set("a", "1").unwrap();
+		client.get("a").unwrap();
+		client.list_prefix("").unwrap();
+
+		let requests = server.requests();
+		assert_eq!(requests.len(), 3);
+		for request in &requests {
+			assert_eq!(request.header("Authorization"), Some("Bearer secret"));
+		}
+	}
+
+	#[test]
+	fn inspect_receives_request_and_response_info() {
+		let server = MockServer::start();
+		let seen = Arc::new(std::sync::Mutex::new(Vec::new()));
+		let hook_seen = seen.clone();
+		let client = Client::new_url(server.url()).inspect(move |request, response| {
+			hook_seen.lock().unwrap().push((request.clone(), response.clone()));
+		});
+		client.set("a", "hello").unwrap();
+		client.get("a").unwrap();
+		client.get("missing").unwrap();
+
+		let seen = seen.lock().unwrap();
+		let summary: Vec<_> = seen.iter()
+			.map(|(request, response)| (
+				request.method.as_str(),
+				request.key.as_deref(),
+				request.body_len,
+				response.status,
+				response.content_length,
+			))
+			.collect();
+		assert_eq!(summary, vec![
+			("POST", Some("a"), "a=hello".len(), Some(200), Some(0)),
+			("GET", Some("a"), 0, Some(200), Some(5)),
+			("GET", Some("missing"), 0, Some(404), Some(0)),
+		]);
+	}
+
+	#[test]
+	fn get_with_modified_parses_last_modified() {
+		let server = MockServer::with_handler(|request, _| match request.key.as_deref() {
+			Some("dated") => Some(MockResponse::status(200)
+				.header("Last-Modified", "Sun, 06 Nov 1994 08:49:37 GMT")
+				.body("1")),
+			_ => None,
+		});
+		server.insert("undated", "2");
+		let client = Client::new_url(server.url());
+
+		let (value, modified) = client.get_with_modified("dated").unwrap().unwrap();
+		assert_eq!(value, "1");
+		assert_eq!(modified, Some(UNIX_EPOCH + Duration::from_secs(784_111_777)));
+		let undated = client.get_with_modified("undated").unwrap();
+		assert_eq!(undated, Some(("2".to_string(), None)));
+		assert_eq!(client.get_with_modified("missing").unwrap(), None);
+	}
+
+	#[test]
+	fn empty_values_are_distinct_from_missing_keys() {
+		let server = MockServer::start();
+		server.insert("empty", "");
+		let client = Client::new_url(server.url());
+
+		assert_eq!(client.get("empty").unwrap().as_deref(), Some(""));
+		assert_eq!(client.get("missing").unwrap(), None);
+	}
+
+	#[test]
+	fn get_all_as_maps_each_value() {
+		let server = MockServer::start();
+		server.insert("a", "1");
+		server.insert("b", "-20");
+		let client = Client::new_url(server.url());
+
+		let values = client.get_all_as(|value| value.parse::<i64>().unwrap()).unwrap();
+		assert_eq!(values.len(), 2);
+		assert_eq!(values["a"], 1);
+		assert_eq!(values["b"], -20);
+	}
+
+	#[test]
+	fn empty_except_keeps_the_listed_keys() {
+		let server = MockServer::start();
+		for key in &[ "a", "b", "c", "d" ] {
+			server.insert(key, "1");
+		}
+		let client = Client::new_url(server.url());
+
+		let keep: HashSet<String> = vec![ "b".to_string(), "d".to_string() ].into_iter().collect();
+		assert_eq!(client.empty_except(&keep).unwrap(), 2);
+		assert_eq!(server.values().keys().collect::<Vec<_>>(), vec![ "b", "d" ]);
+	}
+
+	#[test]
+	fn list_matching_filters_by_prefix_and_suffix() {
+		let server = MockServer::start();
+		for key in &[ "user:1.json", "user:2.txt", "user:3.json", "post:4.json" ] {
+			server.insert(key, "1");
+		}
+		let client = Client::new_url(server.url());
+
+		let keys = client.list_matching("user:", ".json").unwrap();
+		assert_eq!(keys, vec![ "user:1.json", "user:3.json" ]);
+	}
+
+	#[test]
+	fn clone_with_url_keeps_options() {
+		let first = MockServer::start();
+		let second = MockServer::start();
+		let client = Client::new_url(first.url()).bearer_token("secret");
+
+		client.clone_with_url(second.url()).set("a", "1").unwrap();
+		assert!(first.requests().is_empty());
+		assert_eq!(second.values()["a"], "1");
+		assert_eq!(second.requests()[0].header("Authorization"), Some("Bearer secret"));
+	}
+
+	#[test]
+	fn increment_float_adds_to_the_current_value() {
+		let server = MockServer::start();
+		server.insert("existing", "1.5");
+		server.insert("text", "abc");
+		let client = Client::new_url(server.url());
+
+		assert_eq!(client.increment_float("new", 0.25).unwrap(), 0.25);
+		assert_eq!(client.increment_float("existing", 0.25).unwrap(), 1.75);
+		assert_eq!(server.values()["new"], "0.25");
+		assert_eq!(server.values()["existing"], "1.75");
+		let result = client.increment_float("text", 1.0);
+		assert!(matches!(result, Err(Error::Parse(value)) if value == "abc"));
+		assert_eq!(server.values()["text"], "abc");
+	}
+
+	#[test]
+	fn trim_values_strips_surrounding_whitespace() {
+		let server = MockServer::start();
+		server.insert("a", "value\n");
+
+		let client = Client::new_url(server.url());
+		assert_eq!(client.get("a").unwrap().as_deref(), Some("value\n"));
+		let client = client.trim_values(true);
+		assert_eq!(client.get("a").unwrap().as_deref(), Some("value"));
+	}
+
+	#[test]
+	fn get_first_stops_at_the_first_present_key() {
+		let server = MockServer::start();
+		server.insert("b", "2");
+		server.insert("c", "3");
+		let client = Client::new_url(server.url());
+
+		let first = client.get_first(&[ "a", "b", "c" ]).unwrap();
+		assert_eq!(first, Some(("b".to_string(), "2".to_string())));
+		let keys: Vec<_> = server.requests().into_iter()
+			.map(|request| request.key.unwrap())
+			.collect();
+		assert_eq!(keys, vec![ "a", "b" ]);
+	}
+
+	#[test]
+	fn exists_many_reports_each_key() {
+		let server = MockServer::start();
+		server.insert("a", "1");
+		server.insert("c", "");
+		let client = Client::new_url(server.url());
+
+		let exists = client.exists_many(vec![ "a", "b", "c" ]).unwrap();
+		assert_eq!(exists.len(), 3);
+		assert!(exists["a"]);
+		assert!(!exists["b"]);
+		assert!(exists["c"]);
+	}
+
+	#[test]
+	fn get_all_with_progress_reports_each_fetch() {
+		let server = MockServer::start();
+		for key in &[ "a", "b", "c" ] {
+			server.insert(key, "1");
+		}
+		let client = Client::new_url(server.url());
+
+		let calls = std::cell::RefCell::new(Vec::new());
+		let progress = |fetched, total| calls.borrow_mut().push((fetched, total));
+		let values = client.get_all_with_progress(progress).unwrap();
+		assert_eq!(values.len(), 3);
+		assert_eq!(calls.into_inner(), vec![ (0, 3), (1, 3), (2, 3), (3, 3) ]);
+	}
+
+	#[test]
+	fn set_display_stores_the_formatted_value() {
+		struct Point(i32, i32);
+
+		impl Display for Point {
+			fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+				write!(f, "({}, {})", self.0, self.1)
+			}
+		}
+
+		let server = MockServer::start();
+		let client = Client::new_url(server.url());
+		client.set_display("count", 42).unwrap();
+		client.set_display("point", Point(1, -2)).unwrap();
+
+		let values = server.values();
+		assert_eq!(values["count"], "42");
+		assert_eq!(values["point"], "(1, -2)");
+	}
+
+	#[test]
+	fn delete_many_sends_one_delete_per_key() {
+		let server = MockServer::start();
+		for key in &[ "a", "b", "c" ] {
+			server.insert(key, "1");
+		}
+		let client = Client::new_url(server.url());
+
+		client.delete_many(vec![ "a", "c" ]).unwrap();
+		assert_eq!(server.values().keys().collect::<Vec<_>>(), vec![ "b" ]);
+		let mut deleted: Vec<_> = server.requests().into_iter()
+			.filter(|request| request.method == "DELETE")
+			.map(|request| request.key.unwrap())
+			.collect();
+		deleted.sort();
+		assert_eq!(deleted, vec![ "a", "c" ]);
+	}
+
+	#[test]
+	fn delete_many_stops_at_the_first_failure() {
+		let server = MockServer::with_handler(|request, _| match (request.method.as_str(), request.key.as_deref()) {
+			("DELETE", Some("b")) => Some(MockResponse::status(500)),
+			_ => None,
+		});
+		for key in &[ "a", "b", "c" ] {
+			server.insert(key, "1");
+		}
+		let client = Client::new_url(server.url());
+
+		let result = client.delete_many(vec![ "a", "b", "c" ]);
+		assert!(matches!(result, Err(Error::Server { status: 500, .. })));
+		assert_eq!(server.values().keys().collect::<Vec<_>>(), vec![ "b", "c" ]);
+	}
+
+	#[test]
+	fn list_prefix_limited_returns_at_most_limit_keys() {
+		let server = MockServer::start();
+		for key in &[ "a1", "a2", "a3", "b1" ] {
+			server.insert(key, "1");
+		}
+		let client = Client::new_url(server.url());
+
+		assert_eq!(client.list_prefix_limited("a", 2).unwrap(), vec![ "a1", "a2" ]);
+		assert_eq!(client.list_prefix_limited("a", 10).unwrap(), vec![ "a1", "a2", "a3" ]);
+		assert!(client.list_prefix_limited("a", 0).unwrap().is_empty());
+	}
+
+	#[test]
+	fn put_sets_or_deletes() {
+		let server = MockServer::start();
+		server.insert("b", "old");
+		let client = Client::new_url(server.url());
+
+		client.put("a", Some("1")).unwrap();
+		client.put("b", None::<String>).unwrap();
+		let values = server.values();
+		assert_eq!(values.len(), 1);
+		assert_eq!(values["a"], "1");
+	}
+}